@@ -0,0 +1,566 @@
+//! Terminal UI for switching between recipe alternatives and editing targets and inputs.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use foxhole_production_calculator::saved::Selection;
+use foxhole_production_calculator::stockpile::Stockpile;
+use foxhole_production_calculator::{FactoryRequirements, ResourceGraph, StructureTree};
+use foxhole_production_calculator_types::Material;
+use indextree::NodeId;
+use tui::backend::{Backend, CrosstermBackend};
+use tui::layout::{Constraint, Direction, Layout};
+use tui::style::{Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, Cell, List, ListItem, ListState, Paragraph, Row, Table};
+use tui::{Frame, Terminal};
+
+use crate::parse_target;
+
+const HELP: &str =
+    "up/down: select  left/right: switch alternative  t: set target  u: toggle input  q: quit";
+
+/// A line of the tree view, pointing at an active node.
+struct TreeRow {
+    tree: usize,
+    node: NodeId,
+    label: String,
+}
+
+#[derive(Clone, Copy)]
+enum PromptKind {
+    Target,
+    Input,
+}
+
+struct Prompt {
+    kind: PromptKind,
+    text: String,
+}
+
+struct App {
+    /// Graph the trees were planned with, along with any preferences from a plan.
+    rg: ResourceGraph,
+    /// Capped inputs the trees were planned with. Every recalculation starts from the full caps.
+    capped_inputs: HashMap<Material, u64>,
+    targets: BTreeMap<Material, u64>,
+    user_inputs: BTreeSet<Material>,
+    trees: Vec<StructureTree>,
+    reqs: FactoryRequirements,
+    rows: Vec<TreeRow>,
+    list: ListState,
+    prompt: Option<Prompt>,
+    status: String,
+}
+
+impl App {
+    fn new(
+        rg: ResourceGraph,
+        capped_inputs: HashMap<Material, u64>,
+        trees: Vec<StructureTree>,
+        user_inputs: Vec<Material>,
+    ) -> Self {
+        let targets = trees
+            .iter()
+            .filter_map(|tree| tree.target())
+            .map(|target| (target.material, target.value))
+            .collect();
+        let reqs =
+            rg.factory_requirements_from_trees(&trees, user_inputs.iter().copied().collect());
+        let user_inputs = user_inputs.into_iter().collect();
+
+        let mut app = Self {
+            rg,
+            capped_inputs,
+            targets,
+            user_inputs,
+            trees,
+            reqs,
+            rows: Vec::new(),
+            list: ListState::default(),
+            prompt: None,
+            status: HELP.to_string(),
+        };
+        app.refresh();
+
+        app
+    }
+
+    /// Recalculate the requirements and tree view after a change to the trees.
+    fn refresh(&mut self) {
+        self.reqs = self.rg.factory_requirements_from_trees(
+            &self.trees,
+            self.user_inputs.iter().copied().collect(),
+        );
+
+        self.rows.clear();
+        for (tree_idx, tree) in self.trees.iter().enumerate() {
+            let mut stack: Vec<NodeId> = tree.roots.iter().flatten().rev().copied().collect();
+            while let Some(node_id) = stack.pop() {
+                let details = self
+                    .rg
+                    .node_details(tree, node_id)
+                    .expect("Node should exist");
+                if !details.active {
+                    continue;
+                }
+
+                let building = match &details.upgrade {
+                    Some(upgrade) => format!("{} ({})", details.building, upgrade),
+                    None => details.building.clone(),
+                };
+                let mut label = format!(
+                    "{}{:.2}x {} -> {} {:.2}/h",
+                    "  ".repeat(details.depth),
+                    details.count,
+                    building,
+                    details.output.material,
+                    details.output.rate
+                );
+                if details.alternatives.len() > 1 {
+                    let idx = details
+                        .alternatives
+                        .iter()
+                        .position(|alternative| *alternative == node_id)
+                        .expect("Node should be one of its alternatives");
+                    label.push_str(&format!("  [{}/{}]", idx + 1, details.alternatives.len()));
+                }
+
+                self.rows.push(TreeRow {
+                    tree: tree_idx,
+                    node: node_id,
+                    label,
+                });
+                stack.extend(details.children.iter().rev());
+            }
+        }
+
+        let selected = self.list.selected().unwrap_or_default();
+        self.list.select(if self.rows.is_empty() {
+            None
+        } else {
+            Some(selected.min(self.rows.len() - 1))
+        });
+    }
+
+    /// Plan the targets again, keeping the alternatives chosen for targets that are still
+    /// there.
+    fn recalculate(&mut self) {
+        let selections: HashMap<Material, Vec<Selection>> = self
+            .trees
+            .iter()
+            .filter_map(StructureTree::save)
+            .map(|saved| (saved.target.material, saved.selections))
+            .collect();
+
+        let targets = self.targets.iter().map(|(k, v)| (*k, *v)).collect();
+        let user_inputs = self.user_inputs.iter().copied().collect();
+        self.trees = self.rg.calculate_factory_requirements_with_supply(
+            targets,
+            user_inputs,
+            &self.capped_inputs,
+            &Stockpile::default(),
+        );
+        for tree in &mut self.trees {
+            let material = tree.target().map(|target| target.material);
+            if let Some(selections) = material.and_then(|material| selections.get(&material)) {
+                tree.apply_selections(selections);
+            }
+        }
+
+        self.refresh();
+    }
+
+    /// Activate the next, or previous, alternative to the selected node.
+    fn cycle_alternative(&mut self, forward: bool) {
+        let row = match self.list.selected().and_then(|idx| self.rows.get(idx)) {
+            Some(row) => row,
+            None => return,
+        };
+        let (tree_idx, node_id) = (row.tree, row.node);
+        let tree = &mut self.trees[tree_idx];
+        let node = tree.get_node(node_id).expect("Node should exist");
+        let alternatives = tree.options(node).to_vec();
+        if alternatives.len() < 2 {
+            self.status = "No alternatives for this step".to_string();
+            return;
+        }
+
+        let idx = alternatives
+            .iter()
+            .position(|alternative| *alternative == node_id)
+            .expect("Node should be one of its alternatives");
+        let next = if forward {
+            (idx + 1) % alternatives.len()
+        } else {
+            (idx + alternatives.len() - 1) % alternatives.len()
+        };
+        tree.activate_node(alternatives[next]);
+        self.status = HELP.to_string();
+
+        self.refresh();
+        if let Some(row) = self
+            .rows
+            .iter()
+            .position(|row| row.tree == tree_idx && row.node == alternatives[next])
+        {
+            self.list.select(Some(row));
+        }
+    }
+
+    fn submit_prompt(&mut self, prompt: Prompt) {
+        let text = prompt.text.trim();
+        let result = match prompt.kind {
+            PromptKind::Target => parse_target(text).map(|(material, rate)| {
+                if rate == 0 {
+                    self.targets.remove(&material);
+                } else {
+                    self.targets.insert(material, rate);
+                }
+            }),
+            PromptKind::Input => {
+                Material::parse_loose(text)
+                    .map_err(|e| e.to_string())
+                    .map(|material| {
+                        if !self.user_inputs.remove(&material) {
+                            self.user_inputs.insert(material);
+                        }
+                    })
+            }
+        };
+
+        match result {
+            Ok(()) => {
+                self.status = HELP.to_string();
+                self.recalculate();
+            }
+            Err(e) => self.status = format!("error: {}", e),
+        }
+    }
+
+    /// Handle a key press. Returns `false` once the user asks to quit.
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        if let Some(mut prompt) = self.prompt.take() {
+            match key.code {
+                KeyCode::Enter => self.submit_prompt(prompt),
+                KeyCode::Esc => self.status = HELP.to_string(),
+                KeyCode::Backspace => {
+                    prompt.text.pop();
+                    self.prompt = Some(prompt);
+                }
+                KeyCode::Char(c) => {
+                    prompt.text.push(c);
+                    self.prompt = Some(prompt);
+                }
+                _ => self.prompt = Some(prompt),
+            }
+
+            return true;
+        }
+
+        let selected = self.list.selected().unwrap_or_default();
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Up | KeyCode::Char('k') => self.list.select(Some(selected.saturating_sub(1))),
+            KeyCode::Down | KeyCode::Char('j') if selected + 1 < self.rows.len() => {
+                self.list.select(Some(selected + 1))
+            }
+            KeyCode::Right | KeyCode::Char('l') => self.cycle_alternative(true),
+            KeyCode::Left | KeyCode::Char('h') => self.cycle_alternative(false),
+            KeyCode::Char('t') => {
+                self.prompt = Some(Prompt {
+                    kind: PromptKind::Target,
+                    text: String::new(),
+                })
+            }
+            KeyCode::Char('u') => {
+                self.prompt = Some(Prompt {
+                    kind: PromptKind::Input,
+                    text: String::new(),
+                })
+            }
+            _ => {}
+        }
+
+        true
+    }
+
+    fn draw<B: Backend>(&mut self, f: &mut Frame<B>) {
+        let outer = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(3), Constraint::Length(3)])
+            .split(f.size());
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(55), Constraint::Percentage(45)])
+            .split(outer[0]);
+        let panels = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Percentage(40),
+                Constraint::Percentage(30),
+                Constraint::Percentage(30),
+            ])
+            .split(columns[1]);
+
+        let targets: Vec<String> = self
+            .targets
+            .iter()
+            .map(|(material, rate)| format!("{} {}/h", material, rate))
+            .collect();
+        let items: Vec<ListItem> = self
+            .rows
+            .iter()
+            .map(|row| ListItem::new(row.label.as_str()))
+            .collect();
+        let tree = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!("Production Chain: {}", targets.join(", "))),
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        f.render_stateful_widget(tree, columns[0], &mut self.list);
+
+        let buildings: Vec<Row> = self
+            .reqs
+            .buildings
+            .iter()
+            .map(|building| {
+                Row::new(vec![
+                    Cell::from(building.building.as_str()),
+                    Cell::from(building.upgrade.as_deref().unwrap_or("-")),
+                    Cell::from(format!("{:.2}", building.count)),
+                ])
+            })
+            .collect();
+        let buildings = Table::new(buildings)
+            .header(
+                Row::new(vec!["Building", "Upgrade", "Count"])
+                    .style(Style::default().add_modifier(Modifier::BOLD)),
+            )
+            .block(Block::default().borders(Borders::ALL).title("Buildings"))
+            .widths(&[
+                Constraint::Percentage(40),
+                Constraint::Percentage(40),
+                Constraint::Percentage(20),
+            ]);
+        f.render_widget(buildings, panels[0]);
+
+        let inputs: BTreeMap<_, _> = self.reqs.inputs.iter().collect();
+        let inputs: Vec<ListItem> = inputs
+            .into_iter()
+            .map(|(material, rate)| {
+                let supplied = if self.user_inputs.contains(material) {
+                    " (supplied)"
+                } else {
+                    ""
+                };
+                ListItem::new(format!("{}: {:.2}/h{}", material, rate, supplied))
+            })
+            .collect();
+        let inputs =
+            List::new(inputs).block(Block::default().borders(Borders::ALL).title("Inputs"));
+        f.render_widget(inputs, panels[1]);
+
+        let mut cost = vec![Spans::from(format!("Power: {:.2} MW", self.reqs.power))];
+        let build_cost: BTreeMap<_, _> = self.reqs.build_cost.iter().collect();
+        for (material, amount) in build_cost {
+            cost.push(Spans::from(format!("{}: {}", material, amount)));
+        }
+        let cost = Paragraph::new(cost).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Power and Build Cost"),
+        );
+        f.render_widget(cost, panels[2]);
+
+        let footer = match &self.prompt {
+            Some(prompt) => {
+                let label = match prompt.kind {
+                    PromptKind::Target => "Target as MATERIAL=RATE, 0 removes it: ",
+                    PromptKind::Input => "Toggle supplied input MATERIAL: ",
+                };
+                Spans::from(vec![
+                    Span::styled(label, Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(prompt.text.as_str()),
+                ])
+            }
+            None => Spans::from(self.status.as_str()),
+        };
+        f.render_widget(
+            Paragraph::new(footer).block(Block::default().borders(Borders::ALL)),
+            outer[1],
+        );
+    }
+}
+
+/// Take over the terminal until the user quits. Returns the trees as the user left them.
+pub fn run(
+    rg: ResourceGraph,
+    capped_inputs: HashMap<Material, u64>,
+    trees: Vec<StructureTree>,
+    user_inputs: Vec<Material>,
+) -> io::Result<Vec<StructureTree>> {
+    let mut app = App::new(rg, capped_inputs, trees, user_inputs);
+
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+
+    let result = (|| loop {
+        terminal.draw(|f| app.draw(f))?;
+        if let Event::Key(key) = event::read()? {
+            if !app.handle_key(key) {
+                return Ok(());
+            }
+        }
+    })();
+
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+
+    result.map(|()| app.trees)
+}
+
+#[cfg(test)]
+mod test {
+    use foxhole_production_calculator::plan::Plan;
+    use foxhole_production_calculator::preferences::RecipePreferences;
+
+    use super::*;
+
+    fn press(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            app.handle_key(KeyCode::Char(c).into());
+        }
+    }
+
+    fn pipe_app() -> App {
+        let rg = ResourceGraph::default();
+        let targets = vec![(Material::Pipe, 100)].into_iter().collect();
+        let user_inputs = vec![Material::Components];
+        let trees =
+            rg.calculate_factory_requirements(targets, user_inputs.iter().copied().collect());
+
+        App::new(rg, HashMap::new(), trees, user_inputs)
+    }
+
+    #[test]
+    fn test_key_handling() {
+        let mut app = pipe_app();
+        assert_eq!(app.list.selected(), Some(0));
+
+        app.handle_key(KeyCode::Down.into());
+        assert_eq!(app.list.selected(), Some(1));
+        press(&mut app, "k");
+        assert_eq!(app.list.selected(), Some(0));
+
+        // Keys typed into a prompt don't move the selection or quit.
+        press(&mut app, "tbogus=1q");
+        assert_eq!(app.prompt.as_ref().unwrap().text, "bogus=1q");
+        app.handle_key(KeyCode::Enter.into());
+        assert!(app.prompt.is_none());
+        assert!(app.status.starts_with("error: "));
+        assert_eq!(app.targets.len(), 1);
+
+        press(&mut app, "tpipe=0");
+        app.handle_key(KeyCode::Enter.into());
+        assert!(app.targets.is_empty());
+        assert!(app.rows.is_empty());
+        assert_eq!(app.list.selected(), None);
+
+        assert!(!app.handle_key(KeyCode::Char('q').into()));
+    }
+
+    #[test]
+    fn test_recalculate_keeps_alternatives() {
+        let mut app = pipe_app();
+        let row = app
+            .rows
+            .iter()
+            .position(|row| row.label.ends_with("[1/2]"))
+            .unwrap();
+        app.list.select(Some(row));
+        app.handle_key(KeyCode::Right.into());
+        let chosen = app.trees[0].save().unwrap().selections;
+
+        press(&mut app, "uwater");
+        app.handle_key(KeyCode::Enter.into());
+
+        assert!(app.user_inputs.contains(&Material::Water));
+        assert_eq!(app.trees[0].save().unwrap().selections, chosen);
+    }
+
+    #[test]
+    fn test_recalculate_keeps_plan() {
+        let mut plan = Plan::new(vec![(Material::Pipe, 100)].into_iter().collect());
+        plan.inputs.insert(Material::Components);
+        plan.capped_inputs
+            .insert(Material::ConstructionMaterials, 100);
+        plan.preferences = RecipePreferences::default().exclude_structure("Oil Refinery");
+        let resolved = ResourceGraph::default().resolve_plan(&plan).unwrap();
+        let rg = ResourceGraph::default().with_preferences(plan.preferences.clone());
+        let mut app = App::new(
+            rg,
+            plan.capped_inputs(),
+            resolved.trees,
+            plan.inputs.iter().copied().collect(),
+        );
+        let inputs = app.reqs.inputs.clone();
+        assert!(inputs.contains_key(&Material::ConstructionMaterials));
+
+        press(&mut app, "tpipe=100");
+        app.handle_key(KeyCode::Enter.into());
+
+        assert_eq!(app.reqs.inputs, inputs);
+        assert!(app
+            .reqs
+            .buildings
+            .iter()
+            .all(|building| building.building != "Oil Refinery"));
+    }
+
+    #[test]
+    fn test_plan_written_after_changes() {
+        let mut plan = Plan::new(vec![(Material::Pipe, 100)].into_iter().collect());
+        plan.inputs.insert(Material::Components);
+        plan.capped_inputs
+            .insert(Material::ConstructionMaterials, 100);
+        plan.preferences = RecipePreferences::default().exclude_structure("Oil Refinery");
+        let resolved = ResourceGraph::default().resolve_plan(&plan).unwrap();
+        let mut app = App::new(
+            resolved.graph,
+            plan.capped_inputs(),
+            resolved.trees,
+            plan.inputs.iter().copied().collect(),
+        );
+
+        let row = app
+            .rows
+            .iter()
+            .position(|row| row.label.ends_with(']'))
+            .unwrap();
+        app.list.select(Some(row));
+        app.handle_key(KeyCode::Right.into());
+        press(&mut app, "tpipe=50");
+        app.handle_key(KeyCode::Enter.into());
+
+        let written = crate::plan_from_trees(Some(&resolved.plan), &app.trees);
+        assert_eq!(
+            written.targets,
+            vec![(Material::Pipe, 50)].into_iter().collect()
+        );
+        assert_eq!(written.selections, app.trees[0].save().unwrap().selections);
+        assert_ne!(written.selections, resolved.plan.selections);
+        assert_eq!(written.capped_inputs, plan.capped_inputs);
+        assert_eq!(written.preferences, plan.preferences);
+    }
+}
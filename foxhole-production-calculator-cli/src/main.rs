@@ -1,36 +1,513 @@
-use clap::Parser;
-use foxhole_production_calculator::ResourceGraph;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::{CommandFactory, ErrorKind, Parser, Subcommand, ValueEnum};
+use foxhole_production_calculator::catalog::{self, Recipe};
+use foxhole_production_calculator::csv::CsvSection;
+use foxhole_production_calculator::details::Flow;
+use foxhole_production_calculator::document::RequirementsDocument;
+use foxhole_production_calculator::dot::DotOptions;
+use foxhole_production_calculator::goals::QuantityGoal;
+use foxhole_production_calculator::plan::Plan;
+use foxhole_production_calculator::report::ReportFormat;
+use foxhole_production_calculator::{FactoryRequirementsBuilding, ResourceGraph, StructureTree};
 use foxhole_production_calculator_types::Material;
 
+mod interactive;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Json,
+    Markdown,
+    Text,
+    Csv,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Section {
+    Buildings,
+    Inputs,
+    BuildCost,
+    StockpileUsage,
+    Chain,
+}
+
+impl From<Section> for CsvSection {
+    fn from(section: Section) -> Self {
+        match section {
+            Section::Buildings => CsvSection::Buildings,
+            Section::Inputs => CsvSection::Inputs,
+            Section::BuildCost => CsvSection::BuildCost,
+            Section::StockpileUsage => CsvSection::StockpileUsage,
+            Section::Chain => CsvSection::Chain,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List every material, with the name to use for it on the command line and its shorthand.
+    Materials,
+    /// List every structure and its upgrades, with their build costs.
+    Structures,
+    /// List every production channel making a material.
+    Recipe {
+        #[clap(value_parser = Material::parse_loose)]
+        material: Material,
+    },
+    /// List every production channel consuming a material.
+    Uses {
+        #[clap(value_parser = Material::parse_loose)]
+        material: Material,
+    },
+}
+
 /// Command utility for calculating the most efficient factory configuration for
 /// a target output.
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
+#[clap(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    /// Specifies the output material for the factory.
-    #[clap(arg_enum, value_parser)]
-    material: Material,
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Specifies the output material for the factory. Names are matched loosely, so "Basic
+    /// Materials", basic-materials and bmats all work. See the materials command for every name.
+    #[clap(
+        value_parser = Material::parse_loose,
+        required_unless_present_any = &["targets", "targets-file", "plan"]
+    )]
+    material: Option<Material>,
 
     /// Specifies the desired rate of output for the given material. [Unit/Hour]
     #[clap(value_parser)]
-    rate: u64,
+    rate: Option<u64>,
+
+    /// Additional output materials and their rates, as MATERIAL=RATE. All targets are planned
+    /// together. Multiple values can be input with comma seperators.
+    #[clap(short, long, value_parser = parse_target, use_value_delimiter = true)]
+    targets: Option<Vec<(Material, u64)>>,
+
+    /// File listing targets as MATERIAL=RATE, one per line. Blank lines and lines starting with
+    /// '#' are ignored.
+    #[clap(long, value_parser)]
+    targets_file: Option<PathBuf>,
+
+    /// Total amount of the material to produce, instead of a rate. Requires either --hours or
+    /// --buildings.
+    #[clap(
+        short,
+        long,
+        value_parser,
+        requires = "material",
+        conflicts_with_all = &["rate", "targets", "targets-file"]
+    )]
+    quantity: Option<u64>,
+
+    /// Hours available to produce --quantity. The factory is sized to finish in time.
+    #[clap(long, value_parser = parse_hours, requires = "quantity")]
+    hours: Option<f32>,
+
+    /// Buildings available to produce --quantity, as BUILDING[:UPGRADE]=COUNT. Prints how long
+    /// production will take instead of the factory requirements. Multiple values can be input
+    /// with comma seperators.
+    #[clap(
+        short,
+        long,
+        value_parser = parse_building,
+        use_value_delimiter = true,
+        requires = "quantity",
+        conflicts_with = "hours"
+    )]
+    buildings: Option<Vec<FactoryRequirementsBuilding>>,
+
+    /// Plan file to calculate, in TOML or, with a .json extension, JSON. Holds the targets,
+    /// supplied inputs with optional caps, recipe preferences and chosen alternatives.
+    #[clap(
+        short,
+        long,
+        value_parser,
+        conflicts_with_all = &["material", "targets", "targets-file", "user-inputs"]
+    )]
+    plan: Option<PathBuf>,
+
+    /// Write the resolved plan, including every alternative chosen, to a TOML or JSON file. With
+    /// --interactive, the plan is written once the interactive view is closed.
+    #[clap(long, value_parser, conflicts_with = "buildings")]
+    write_plan: Option<PathBuf>,
 
     /// Optional argument specifying inputs that will be brought in externally from the factory.
     /// Multiple values can be input with comma seperators.
-    #[clap(short, long, value_parser, use_value_delimiter = true)]
+    #[clap(
+        short,
+        long,
+        value_parser = Material::parse_loose,
+        use_value_delimiter = true
+    )]
     user_inputs: Option<Vec<Material>>,
+
+    /// Format to print the factory requirements in.
+    #[clap(short, long, value_enum, default_value = "json")]
+    format: OutputFormat,
+
+    /// Section of the factory requirements to print with --format csv. The chain lists every
+    /// active building with a reference to the building it supplies.
+    #[clap(long, value_enum, default_value = "buildings")]
+    section: Section,
+
+    /// Open an interactive view of the production chain, to switch between alternatives and
+    /// edit targets and supplied inputs.
+    #[clap(short, long, value_parser, conflicts_with_all = &["quantity", "dot"])]
+    interactive: bool,
+
+    /// Print the production chain as a Graphviz DOT graph instead of the factory requirements.
+    #[clap(long, value_parser, conflicts_with = "buildings")]
+    dot: bool,
+
+    /// Draw a single node for each kind of building in the DOT graph.
+    #[clap(long, value_parser, requires = "dot")]
+    collapse_duplicates: bool,
+
+    /// Also draw inactive alternatives, dashed, in the DOT graph.
+    #[clap(long, value_parser, requires = "dot")]
+    show_inactive: bool,
+}
+
+fn parse_building(value: &str) -> Result<FactoryRequirementsBuilding, String> {
+    let (name, text) = value
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected BUILDING[:UPGRADE]=COUNT, got '{}'", value))?;
+    let count = text
+        .trim()
+        .parse::<f32>()
+        .map_err(|e| format!("invalid building count '{}': {}", text, e))?;
+    if !(count.is_finite() && count > 0.0) {
+        return Err(format!(
+            "building count must be a positive number, got '{}'",
+            text
+        ));
+    }
+    let (building, upgrade) = match name.split_once(':') {
+        Some((building, upgrade)) => (building, Some(upgrade.trim().to_string())),
+        None => (name, None),
+    };
+
+    Ok(FactoryRequirementsBuilding {
+        building: building.trim().to_string(),
+        upgrade,
+        count,
+    })
+}
+
+fn parse_hours(value: &str) -> Result<f32, String> {
+    let hours = value
+        .trim()
+        .parse::<f32>()
+        .map_err(|e| format!("invalid hours '{}': {}", value, e))?;
+    if !(hours.is_finite() && hours > 0.0) {
+        return Err(format!("hours must be a positive number, got '{}'", value));
+    }
+
+    Ok(hours)
+}
+
+fn parse_target(value: &str) -> Result<(Material, u64), String> {
+    let (material, rate) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected MATERIAL=RATE, got '{}'", value))?;
+    let material = Material::parse_loose(material).map_err(|e| e.to_string())?;
+    let rate = rate
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| format!("invalid rate '{}': {}", rate, e))?;
+
+    Ok((material, rate))
+}
+
+fn read_targets(path: &PathBuf) -> Result<Vec<(Material, u64)>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("could not read '{}': {}", path.display(), e))?;
+
+    contents
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(idx, line)| {
+            parse_target(line).map_err(|e| format!("{}:{}: {}", path.display(), idx + 1, e))
+        })
+        .collect()
+}
+
+fn print_materials() {
+    for info in catalog::material_infos() {
+        let line = format!(
+            "{:<34}{:<34}{}",
+            info.cli_name,
+            info.name,
+            info.aliases.join(", ")
+        );
+        println!("{}", line.trim_end());
+    }
+}
+
+fn format_costs<'a>(costs: impl IntoIterator<Item = (&'a Material, &'a u64)>) -> String {
+    let costs: Vec<String> = costs
+        .into_iter()
+        .map(|(material, cost)| format!("{} {}", cost, material))
+        .collect();
+
+    costs.join(", ")
+}
+
+fn format_flows(flows: &[Flow]) -> String {
+    let flows: Vec<String> = flows
+        .iter()
+        .map(|flow| format!("{} {:.2}/h", flow.material, flow.rate))
+        .collect();
+
+    flows.join(", ")
+}
+
+fn print_structures(rg: &ResourceGraph) {
+    for structure in rg.structures() {
+        println!(
+            "{}: {}",
+            structure.building,
+            format_costs(&structure.build_cost)
+        );
+        for upgrade in structure.upgrades {
+            println!(
+                "    {}: {}",
+                upgrade.name,
+                format_costs(&upgrade.build_cost)
+            );
+        }
+    }
+}
+
+fn print_recipes(recipes: Vec<Recipe>, empty: String) {
+    if recipes.is_empty() {
+        println!("{}", empty);
+    }
+
+    for recipe in recipes {
+        let building = match &recipe.upgrade {
+            Some(upgrade) => format!("{} ({})", recipe.building, upgrade),
+            None => recipe.building.clone(),
+        };
+        println!(
+            "{}, channel {}, {:.2} MW",
+            building, recipe.channel, recipe.power
+        );
+        println!("    in:  {}", format_flows(&recipe.inputs));
+        println!("    out: {}", format_flows(&recipe.outputs));
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "json")
+}
+
+fn read_plan(path: &Path) -> Result<Plan, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("could not read '{}': {}", path.display(), e))?;
+    let plan = if is_json(path) {
+        serde_json::from_str(&contents).map_err(|e| e.to_string())
+    } else {
+        toml::from_str(&contents).map_err(|e| e.to_string())
+    };
+
+    plan.map_err(|e| format!("invalid plan '{}': {}", path.display(), e))
+}
+
+fn write_plan(path: &Path, plan: &Plan) -> Result<(), String> {
+    let contents = if is_json(path) {
+        serde_json::to_string_pretty(plan).map_err(|e| e.to_string())?
+    } else {
+        toml::to_string(plan).map_err(|e| e.to_string())?
+    };
+
+    fs::write(path, contents).map_err(|e| format!("could not write '{}': {}", path.display(), e))
+}
+
+/// The plan to write for a set of trees, keeping the preferences and capped inputs of the plan
+/// they were resolved from.
+fn plan_from_trees(plan: Option<&Plan>, trees: &[StructureTree]) -> Plan {
+    match plan {
+        Some(plan) => plan.with_trees(trees),
+        None => Plan::from_trees(trees),
+    }
 }
 
 fn main() {
     let args = Args::parse();
 
-    let rg = ResourceGraph::default();
+    let mut rg = ResourceGraph::default();
+
+    match args.command {
+        Some(Command::Materials) => return print_materials(),
+        Some(Command::Structures) => return print_structures(&rg),
+        Some(Command::Recipe { material }) => {
+            return print_recipes(
+                rg.recipes(material),
+                format!("Nothing produces {}", material),
+            )
+        }
+        Some(Command::Uses { material }) => {
+            return print_recipes(rg.uses(material), format!("Nothing uses {}", material))
+        }
+        None => {}
+    }
+
+    let mut target_list = args.targets.unwrap_or_default();
+    if let Some(path) = &args.targets_file {
+        match read_targets(path) {
+            Ok(file_targets) => target_list.extend(file_targets),
+            Err(e) => Args::command().error(ErrorKind::Io, e).exit(),
+        }
+    }
+    if let (Some(material), Some(rate)) = (args.material, args.rate) {
+        target_list.push((material, rate));
+    }
+    let targets = catalog::sum_targets(target_list)
+        .unwrap_or_else(|e| Args::command().error(ErrorKind::ValueValidation, e).exit());
+
+    let mut user_inputs = args.user_inputs.unwrap_or_default();
+    let mut plan = None;
+    let trees = if let Some(path) = &args.plan {
+        let resolved = read_plan(path)
+            .and_then(|plan| rg.resolve_plan(&plan).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| Args::command().error(ErrorKind::ValueValidation, e).exit());
+        for selection in &resolved.stale {
+            let path: Vec<String> = selection.path.iter().map(Material::to_string).collect();
+            let choice = &selection.choice;
+            eprintln!(
+                "warning: {} {} channel {} can no longer be chosen for {}, using the default",
+                choice.upgrade.building,
+                choice.upgrade.upgrade.as_deref().unwrap_or_default(),
+                choice.channel,
+                path.join(" > ")
+            );
+        }
+
+        user_inputs = resolved.plan.inputs.iter().copied().collect();
+        plan = Some(resolved.plan);
+        // Everything from here on has to follow the plan's preferences.
+        rg = resolved.graph;
+        resolved.trees
+    } else {
+        match (
+            args.material,
+            args.rate,
+            args.quantity,
+            args.hours,
+            args.buildings,
+        ) {
+            (Some(_), Some(_), _, _, _) => rg
+                .calculate_factory_requirements(targets, user_inputs.clone().into_iter().collect()),
+            (Some(material), None, Some(quantity), Some(hours), _) => {
+                let goal = QuantityGoal::new(material, quantity, hours).unwrap_or_else(|e| {
+                    Args::command().error(ErrorKind::ValueValidation, e).exit()
+                });
+                rg.calculate_goal_requirements(&[goal], user_inputs.clone().into_iter().collect())
+            }
+            (Some(material), None, Some(quantity), None, Some(buildings)) => {
+                match rg.estimate_production_time(
+                    material,
+                    quantity,
+                    &buildings,
+                    user_inputs.into_iter().collect(),
+                ) {
+                    Ok(estimate) => {
+                        println!("{}", serde_json::to_string_pretty(&estimate).unwrap())
+                    }
+                    Err(e) => Args::command().error(ErrorKind::ValueValidation, e).exit(),
+                }
+
+                return;
+            }
+            (Some(_), None, Some(_), None, None) => Args::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "--quantity requires either --hours or --buildings",
+                )
+                .exit(),
+            (Some(material), None, None, _, _) => Args::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    format!("a RATE or --quantity is required for {}", material),
+                )
+                .exit(),
+            (None, _, _, _, _) if targets.is_empty() => Args::command()
+                .error(ErrorKind::MissingRequiredArgument, "no targets were given")
+                .exit(),
+            (None, _, _, _, _) => rg
+                .calculate_factory_requirements(targets, user_inputs.clone().into_iter().collect()),
+        }
+    };
+    if args.interactive {
+        let capped_inputs = plan.as_ref().map(Plan::capped_inputs).unwrap_or_default();
+        let trees = interactive::run(rg, capped_inputs, trees, user_inputs)
+            .unwrap_or_else(|e| Args::command().error(ErrorKind::Io, e).exit());
+        if let Some(path) = &args.write_plan {
+            if let Err(e) = write_plan(path, &plan_from_trees(plan.as_ref(), &trees)) {
+                Args::command().error(ErrorKind::Io, e).exit();
+            }
+        }
+
+        return;
+    }
+    if let Some(path) = &args.write_plan {
+        if let Err(e) = write_plan(path, &plan_from_trees(plan.as_ref(), &trees)) {
+            Args::command().error(ErrorKind::Io, e).exit();
+        }
+    }
+    if args.dot {
+        let options = DotOptions {
+            collapse_duplicates: args.collapse_duplicates,
+            show_inactive: args.show_inactive,
+        };
+        print!("{}", rg.to_dot(&trees, &options));
+
+        return;
+    }
 
-    let user_inputs = args.user_inputs.unwrap_or_default();
-    let output = vec![(args.material, args.rate)].into_iter().collect();
-    let trees =
-        rg.calculate_factory_requirements(output, user_inputs.clone().into_iter().collect());
     let reqs = rg.factory_requirements_from_trees(&trees, user_inputs.into_iter().collect());
+    match args.format {
+        OutputFormat::Json => {
+            let document = RequirementsDocument::from(&reqs);
+            println!("{}", serde_json::to_string_pretty(&document).unwrap());
+        }
+        OutputFormat::Markdown => print!("{}", rg.report(&trees, &reqs, ReportFormat::Markdown)),
+        OutputFormat::Text => print!("{}", rg.report(&trees, &reqs, ReportFormat::Text)),
+        OutputFormat::Csv => print!("{}", rg.to_csv(&trees, &reqs, args.section.into())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
 
-    println!("{}", serde_json::to_string_pretty(&reqs).unwrap());
+    #[test]
+    fn test_parse_building() {
+        assert_eq!(
+            parse_building("Materials Factory:Forge=1.5"),
+            Ok(FactoryRequirementsBuilding {
+                building: "Materials Factory".to_string(),
+                upgrade: Some("Forge".to_string()),
+                count: 1.5,
+            })
+        );
+        for count in ["0", "-2", "nan", "inf"] {
+            assert!(parse_building(&format!("Materials Factory={}", count)).is_err());
+        }
+    }
 }
@@ -0,0 +1,65 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use serde_json::Value;
+
+/// Write a plan to a file only this test uses.
+fn write_plan(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "foxhole-production-calculator-cli-{}-{}.toml",
+        name,
+        std::process::id()
+    ));
+    fs::write(&path, contents).unwrap();
+
+    path
+}
+
+fn run(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_foxhole-production-calculator-cli"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_plan_preferences_carry_through_output() {
+    let path = write_plan(
+        "preferences",
+        r#"version = 1
+inputs = ["Components"]
+
+[targets]
+Pipe = 100
+
+[preferences]
+excluded_structures = ["Oil Refinery"]
+"#,
+    );
+    let plan = path.to_str().unwrap();
+
+    // With no Oil Refinery to make it, the Metal Press's Petrol has to be brought in.
+    let document: Value = serde_json::from_str(&run(&["--plan", plan])).unwrap();
+    let buildings = document["buildings"].as_array().unwrap();
+    assert!(buildings
+        .iter()
+        .any(|building| building["upgrade"] == "Metal Press"));
+    assert!(buildings
+        .iter()
+        .all(|building| building["building"] != "Oil Refinery"));
+    assert!(document["inputs"]["Petrol"].as_f64().unwrap() > 0.0);
+
+    let dot = run(&["--plan", plan, "--dot"]);
+    assert!(dot.contains("Metal Press"));
+    assert!(!dot.contains("Oil Refinery"));
+
+    fs::remove_file(path).unwrap();
+}
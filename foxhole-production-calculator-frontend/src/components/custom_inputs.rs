@@ -1,10 +1,9 @@
 use std::collections::HashSet;
-use std::str::FromStr;
 
 use foxhole_production_calculator_types::Material;
 use itertools::sorted;
 use strum::IntoEnumIterator;
-use web_sys::HtmlSelectElement;
+use web_sys::HtmlInputElement;
 use yew::prelude::*;
 
 pub enum CustomInputsMsg {
@@ -20,6 +19,8 @@ pub struct CustomInputsProps {
 pub struct CustomInputs {
     custom_inputs: HashSet<Material>,
     material_ref: NodeRef,
+    /// Why the last material typed couldn't be added.
+    error: Option<String>,
 }
 
 impl Component for CustomInputs {
@@ -30,17 +31,18 @@ impl Component for CustomInputs {
         Self {
             custom_inputs: HashSet::new(),
             material_ref: NodeRef::default(),
+            error: None,
         }
     }
 
     fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
             CustomInputsMsg::NewInput => {
-                let material_element = self.material_ref.cast::<HtmlSelectElement>();
+                let material_element = self.material_ref.cast::<HtmlInputElement>();
 
-                match material_element {
-                    Some(material_element) => {
-                        let material = Material::from_str(&material_element.value()).unwrap();
+                match material_element.map(|element| Material::parse_loose(&element.value())) {
+                    Some(Ok(material)) => {
+                        self.error = None;
                         self.custom_inputs.insert(material);
 
                         let update_callback = &ctx.props().callback;
@@ -48,6 +50,11 @@ impl Component for CustomInputs {
 
                         true
                     }
+                    Some(Err(e)) => {
+                        self.error = Some(e.to_string());
+
+                        true
+                    }
                     None => {
                         log::error!("Can't find material element");
 
@@ -66,6 +73,8 @@ impl Component for CustomInputs {
         }
     }
 
+    // yew 0.19's `html!` expands component props into statements this lint flags.
+    #[allow(clippy::unnecessary_operation)]
     fn view(&self, ctx: &Context<Self>) -> Html {
         let link = ctx.link();
         let remove_callback = link.callback(CustomInputsMsg::RemoveInput);
@@ -83,15 +92,21 @@ impl Component for CustomInputs {
             <label class="label">{ "Outside Inputs:" }</label>
             <div class="field is-grouped">
             <div class="control">
-                <div class="select">
-                    <select ref={self.material_ref.clone()}>
-                        {
-                            material_set_diff.iter().map(|material| {
-                                html! { <option> { material } </option> }
-                            }).collect::<Html>()
-                        }
-                    </select>
-                </div>
+                <input class={classes!("input", self.error.as_ref().map(|_| "is-danger"))}
+                    list="input-materials" placeholder="Material, e.g. cmats"
+                    ref={self.material_ref.clone()}/>
+                <datalist id="input-materials">
+                    {
+                        material_set_diff.iter().map(|material| {
+                            html! { <option value={material.clone()}/> }
+                        }).collect::<Html>()
+                    }
+                </datalist>
+                {
+                    self.error.iter().map(|error| {
+                        html! { <p class="help is-danger">{ error }</p> }
+                    }).collect::<Html>()
+                }
             </div>
             <div class="control">
                 <button class="button" onclick={link.callback(|_| CustomInputsMsg::NewInput)}>
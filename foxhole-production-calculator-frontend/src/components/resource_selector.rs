@@ -1,10 +1,10 @@
+use std::collections::HashMap;
 use std::string::ToString;
-use std::{collections::HashMap, str::FromStr};
 
-use foxhole_production_calculator_types::Material;
+use foxhole_production_calculator_types::{Material, ParseMaterialError};
 use itertools::sorted;
 use strum::IntoEnumIterator;
-use web_sys::{HtmlInputElement, HtmlSelectElement, InputEvent};
+use web_sys::{HtmlInputElement, InputEvent};
 use yew::prelude::*;
 
 #[allow(clippy::enum_variant_names)]
@@ -22,13 +22,15 @@ pub struct ResourceSelectionProps {
 pub struct ResourceSelection {
     material_ref: NodeRef,
     outputs: HashMap<Material, u64>,
+    /// Why the last material typed couldn't be added.
+    error: Option<String>,
 }
 
 impl ResourceSelection {
-    fn get_material(&self) -> Option<Material> {
-        let material_element = self.material_ref.cast::<HtmlSelectElement>();
+    fn get_material(&self) -> Option<Result<Material, ParseMaterialError>> {
+        let material_element = self.material_ref.cast::<HtmlInputElement>();
 
-        material_element.map(|element| Material::from_str(&element.value()).unwrap())
+        material_element.map(|element| Material::parse_loose(&element.value()))
     }
 }
 
@@ -40,6 +42,7 @@ impl Component for ResourceSelection {
         Self {
             material_ref: NodeRef::default(),
             outputs: HashMap::new(),
+            error: None,
         }
     }
 
@@ -61,21 +64,30 @@ impl Component for ResourceSelection {
 
                 true
             }
-            ResourceSelectionMsg::OutputAdded => {
-                if let Some(material) = self.get_material() {
-                    self.outputs.insert(material, 1);
+            ResourceSelectionMsg::OutputAdded => match self.get_material() {
+                Some(Ok(material)) => {
+                    self.error = None;
+                    self.outputs.entry(material).or_insert(1);
                     props.calculation_callback.emit(self.outputs.clone());
 
                     true
-                } else {
-                    log::error!("Can't parse material");
+                }
+                Some(Err(e)) => {
+                    self.error = Some(e.to_string());
+
+                    true
+                }
+                None => {
+                    log::error!("Can't find material element");
 
                     false
                 }
-            }
+            },
         }
     }
 
+    // yew 0.19's `html!` expands component props into statements this lint flags.
+    #[allow(clippy::unnecessary_operation)]
     fn view(&self, ctx: &Context<Self>) -> Html {
         let link = ctx.link();
         let outputs = &self.outputs;
@@ -97,15 +109,21 @@ impl Component for ResourceSelection {
             <label class="label">{ "Resource Output" }</label>
             <div class="field is-grouped">
                 <div class="control">
-                    <div class="select">
-                        <select ref={self.material_ref.clone()}>
-                            {
-                                material_list.iter().map(|material| {
-                                    html! { <option> { material.to_string() } </option> }
-                                }).collect::<Html>()
-                            }
-                        </select>
-                    </div>
+                    <input class={classes!("input", self.error.as_ref().map(|_| "is-danger"))}
+                        list="output-materials" placeholder="Material, e.g. bmats"
+                        ref={self.material_ref.clone()}/>
+                    <datalist id="output-materials">
+                        {
+                            material_list.iter().map(|material| {
+                                html! { <option value={material.clone()}/> }
+                            }).collect::<Html>()
+                        }
+                    </datalist>
+                    {
+                        self.error.iter().map(|error| {
+                            html! { <p class="help is-danger">{ error }</p> }
+                        }).collect::<Html>()
+                    }
                 </div>
                 <div class="control">
                 <button class="button" onclick={link.callback(|_| ResourceSelectionMsg::OutputAdded)}>
@@ -110,19 +110,19 @@ fn process_node(
     }
 }
 
+// yew 0.19's `html!` expands component props into statements this lint flags.
+#[allow(clippy::unnecessary_operation)]
 fn enumerate_options(
     node: &StructureTreeNode,
     tree: &StructureTree,
     active_callback: Callback<(usize, NodeId)>,
     tree_idx: usize,
 ) -> Html {
-    let options = node.options();
-    let options_ref = options.borrow();
     let mut name_set = HashSet::new();
 
     html! {
         {
-            options_ref
+            tree.options(node)
             .iter()
             .filter_map(|node_id| {
                 let node = tree.get_node(*node_id).expect("Node should exist");
@@ -22,7 +22,7 @@ enum AppMsg {
 }
 
 struct App {
-    resource_graph: ResourceGraph<'static>,
+    resource_graph: ResourceGraph,
     custom_inputs: HashSet<Material>,
     buildings: Vec<FactoryRequirementsBuilding>,
     outputs: HashMap<Material, u64>,
@@ -94,6 +94,8 @@ impl Component for App {
         true
     }
 
+    // yew 0.19's `html!` expands component props into statements this lint flags.
+    #[allow(clippy::unnecessary_operation)]
     fn view(&self, ctx: &Context<Self>) -> Html {
         let link = ctx.link();
 
@@ -0,0 +1,247 @@
+//! Python bindings for the foxhole production calculator.
+//!
+//! Results are plain dicts and lists in the same layout as the CLI's JSON output. Material names
+//! in arguments are matched loosely, as on the command line, and results name materials the way
+//! [`Material`] serializes them.
+
+use std::collections::{HashMap, HashSet};
+
+use foxhole_production_calculator::catalog;
+use foxhole_production_calculator::document::RequirementsDocument;
+use foxhole_production_calculator::preferences::RecipePreferences;
+use foxhole_production_calculator::ResourceGraph;
+use foxhole_production_calculator_types::Material;
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict, PyFloat, PyList, PyLong, PyString, PyTuple};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Plans factories against the bundled structure data.
+#[pyclass(name = "ResourceGraph")]
+pub struct PyResourceGraph {
+    rg: ResourceGraph,
+}
+
+#[pymethods]
+impl PyResourceGraph {
+    /// `preferences` is a dict in the layout of a plan file's preferences table.
+    #[new]
+    #[pyo3(signature = (preferences = None))]
+    fn new(preferences: Option<&PyAny>) -> PyResult<Self> {
+        let rg = match preferences {
+            Some(preferences) => {
+                let preferences: RecipePreferences = serde_json::from_value(from_py(preferences)?)
+                    .map_err(|e| PyValueError::new_err(format!("Invalid preferences: {}", e)))?;
+                ResourceGraph::default().with_preferences(preferences)
+            }
+            None => ResourceGraph::default(),
+        };
+
+        Ok(Self { rg })
+    }
+
+    /// Factory requirements for a dict of hourly targets, with `inputs` brought in from outside
+    /// the factory.
+    #[pyo3(signature = (targets, inputs = None))]
+    fn calculate(
+        &self,
+        py: Python<'_>,
+        targets: HashMap<String, u64>,
+        inputs: Option<Vec<String>>,
+    ) -> PyResult<PyObject> {
+        let outputs = targets
+            .iter()
+            .map(|(name, rate)| Ok((parse(name)?, *rate)))
+            .collect::<PyResult<Vec<(Material, u64)>>>()?;
+        let outputs =
+            catalog::sum_targets(outputs).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let user_inputs = inputs
+            .unwrap_or_default()
+            .iter()
+            .map(|name| parse(name))
+            .collect::<PyResult<HashSet<Material>>>()?;
+
+        let trees = self
+            .rg
+            .calculate_factory_requirements(outputs, user_inputs.clone());
+        let reqs = self.rg.factory_requirements_from_trees(&trees, user_inputs);
+
+        to_py(py, &RequirementsDocument::from(&reqs))
+    }
+
+    /// Every structure and its upgrades, with their build costs.
+    fn structures(&self, py: Python<'_>) -> PyResult<PyObject> {
+        to_py(py, &self.rg.structures())
+    }
+
+    /// Every production channel making a material, for a single building.
+    fn recipes(&self, py: Python<'_>, material: &str) -> PyResult<PyObject> {
+        to_py(py, &self.rg.recipes(parse(material)?))
+    }
+
+    /// Every production channel consuming a material, for a single building.
+    fn uses(&self, py: Python<'_>, material: &str) -> PyResult<PyObject> {
+        to_py(py, &self.rg.uses(parse(material)?))
+    }
+}
+
+/// Every material, with its display name, command line name and shorthand.
+#[pyfunction]
+fn materials(py: Python<'_>) -> PyResult<PyObject> {
+    to_py(py, &catalog::material_infos())
+}
+
+/// The name results use for a material given by any name.
+#[pyfunction]
+fn parse_material(name: &str) -> PyResult<String> {
+    Ok(format!("{:?}", parse(name)?))
+}
+
+#[pymodule]
+#[pyo3(name = "foxhole_production_calculator")]
+fn module(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyResourceGraph>()?;
+    m.add_function(wrap_pyfunction!(materials, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_material, m)?)?;
+
+    Ok(())
+}
+
+fn parse(name: &str) -> PyResult<Material> {
+    Material::parse_loose(name).map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// Convert to Python through JSON text, so `f32`s keep their short form rather than picking up
+/// noise from widening to `f64`.
+fn to_py<T: Serialize>(py: Python<'_>, value: &T) -> PyResult<PyObject> {
+    let json = serde_json::to_string(value).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let value: Value =
+        serde_json::from_str(&json).map_err(|e| PyValueError::new_err(e.to_string()))?;
+
+    Ok(value_to_py(py, &value))
+}
+
+fn value_to_py(py: Python<'_>, value: &Value) -> PyObject {
+    match value {
+        Value::Null => py.None(),
+        Value::Bool(b) => b.into_py(py),
+        Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => i.into_py(py),
+            (None, Some(u)) => u.into_py(py),
+            _ => n.as_f64().unwrap_or(f64::NAN).into_py(py),
+        },
+        Value::String(s) => s.into_py(py),
+        Value::Array(items) => {
+            PyList::new(py, items.iter().map(|item| value_to_py(py, item))).into_py(py)
+        }
+        Value::Object(entries) => {
+            let dict = PyDict::new(py);
+            for (key, item) in entries {
+                dict.set_item(key, value_to_py(py, item))
+                    .expect("String keys should always insert");
+            }
+            dict.into_py(py)
+        }
+    }
+}
+
+fn from_py(obj: &PyAny) -> PyResult<Value> {
+    // Bools go before ints, as Python's bools are ints too.
+    if obj.is_none() {
+        Ok(Value::Null)
+    } else if let Ok(b) = obj.downcast::<PyBool>() {
+        Ok(Value::Bool(b.is_true()))
+    } else if obj.is_instance_of::<PyLong>() {
+        Ok(Value::from(obj.extract::<i64>()?))
+    } else if obj.is_instance_of::<PyFloat>() {
+        Ok(Value::from(obj.extract::<f64>()?))
+    } else if let Ok(s) = obj.downcast::<PyString>() {
+        Ok(Value::String(s.to_str()?.to_string()))
+    } else if let Ok(dict) = obj.downcast::<PyDict>() {
+        let mut map = Map::new();
+        for (key, item) in dict {
+            map.insert(key.str()?.to_str()?.to_string(), from_py(item)?);
+        }
+        Ok(Value::Object(map))
+    } else if obj.is_instance_of::<PyList>() || obj.is_instance_of::<PyTuple>() {
+        obj.iter()?
+            .map(|item| from_py(item?))
+            .collect::<PyResult<Vec<Value>>>()
+            .map(Value::Array)
+    } else {
+        Err(PyTypeError::new_err(format!(
+            "Can't convert {} to a preference value",
+            obj.get_type().name()?
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pyo3::types::IntoPyDict;
+
+    use super::*;
+
+    fn run(code: &str) -> PyResult<()> {
+        Python::with_gil(|py| {
+            let module = pyo3::wrap_pymodule!(module)(py);
+            let locals = [("fpc", module)].into_py_dict(py);
+            py.run(code, None, Some(locals))
+        })
+    }
+
+    #[test]
+    fn test_calculate() {
+        run(r#"
+rg = fpc.ResourceGraph()
+reqs = rg.calculate({"cmats": 100}, inputs=["Salvage"])
+assert isinstance(reqs, dict)
+assert reqs["targets"] == {"ConstructionMaterials": 100}
+assert reqs["inputs"]["Salvage"] > 0
+assert all(isinstance(b["count"], float) for b in reqs["buildings"])
+"#)
+        .unwrap();
+    }
+
+    #[test]
+    fn test_catalog() {
+        run(r#"
+materials = fpc.materials()
+assert materials[0] == {"material": "BasicMaterials", "name": "Basic Materials", "cli_name": "basic-materials", "aliases": ["bmats"]}
+assert fpc.parse_material("150s") == "Shell150MM"
+rg = fpc.ResourceGraph()
+assert all(r["building"] for r in rg.recipes("coke"))
+assert any(s["upgrades"] for s in rg.structures())
+"#)
+        .unwrap();
+    }
+
+    #[test]
+    fn test_errors_and_preferences() {
+        run(r#"
+try:
+    fpc.ResourceGraph().uses("shell")
+    raise AssertionError("expected a ValueError")
+except ValueError as e:
+    assert str(e).startswith("Unknown material 'shell', did you mean")
+
+rg = fpc.ResourceGraph({"excluded_structures": ["Coal Refinery"]})
+reqs = rg.calculate({"coke": 10})
+assert "Coal Refinery" not in [b["building"] for b in reqs["buildings"]]
+"#)
+        .unwrap();
+    }
+
+    #[test]
+    fn test_rate_overflow() {
+        run(r#"
+try:
+    fpc.ResourceGraph().calculate({"pipe": 2**64 - 1, "Pipe": 1})
+    raise AssertionError("expected a ValueError")
+except ValueError as e:
+    assert str(e) == "Total rate for Pipe is too large"
+"#)
+        .unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+//! Routes of the HTTP API. Request and response bodies are JSON, and material names anywhere in
+//! a request are matched the same loose way as on the command line.
+
+use std::collections::{BTreeMap, HashSet};
+
+use foxhole_production_calculator::catalog;
+use foxhole_production_calculator::document::RequirementsDocument;
+use foxhole_production_calculator::ResourceGraph;
+use foxhole_production_calculator_types::Material;
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use tiny_http::Method;
+
+/// Body of `POST /calculate`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CalculateRequest {
+    /// Materials to produce. [Unit/Hour]
+    pub targets: BTreeMap<String, u64>,
+    /// Materials brought in from outside the factory.
+    #[serde(default)]
+    pub inputs: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiError {
+    pub status: u16,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+}
+
+impl ApiError {
+    pub fn new(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<ApiError> for ApiResponse {
+    fn from(error: ApiError) -> Self {
+        let body = ErrorBody {
+            error: &error.message,
+        };
+
+        ApiResponse {
+            status: error.status,
+            body: serde_json::to_string(&body).expect("Error body should serialize"),
+        }
+    }
+}
+
+/// Answer a request. `url` may include a query string, which is ignored.
+pub fn handle(rg: &ResourceGraph, method: &Method, url: &str, body: &str) -> ApiResponse {
+    match route(rg, method, url, body) {
+        Ok(body) => ApiResponse { status: 200, body },
+        Err(error) => error.into(),
+    }
+}
+
+fn route(rg: &ResourceGraph, method: &Method, url: &str, body: &str) -> Result<String, ApiError> {
+    let path = url.split('?').next().unwrap_or_default();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    match (method, segments.as_slice()) {
+        (Method::Get, ["materials"]) => to_json(&catalog::material_infos()),
+        (Method::Get, ["structures"]) => to_json(&rg.structures()),
+        (Method::Get, ["recipes", material]) => to_json(&rg.recipes(path_material(material)?)),
+        (Method::Get, ["uses", material]) => to_json(&rg.uses(path_material(material)?)),
+        (Method::Post, ["calculate"]) => {
+            let request: CalculateRequest = serde_json::from_str(body)
+                .map_err(|e| ApiError::new(400, format!("Invalid request body: {}", e)))?;
+            to_json(&calculate(rg, &request)?)
+        }
+        (_, ["materials"] | ["structures"] | ["recipes", _] | ["uses", _] | ["calculate"]) => Err(
+            ApiError::new(405, format!("{} isn't supported on {}", method, path)),
+        ),
+        _ => Err(ApiError::new(404, format!("No route for {}", path))),
+    }
+}
+
+pub fn calculate(
+    rg: &ResourceGraph,
+    request: &CalculateRequest,
+) -> Result<RequirementsDocument, ApiError> {
+    if request.targets.is_empty() {
+        return Err(ApiError::new(400, "At least one target is needed"));
+    }
+
+    let targets = request
+        .targets
+        .iter()
+        .map(|(name, rate)| Ok((material(name)?, *rate)))
+        .collect::<Result<Vec<(Material, u64)>, ApiError>>()?;
+    let targets = catalog::sum_targets(targets).map_err(|e| ApiError::new(400, e.to_string()))?;
+    let inputs = request
+        .inputs
+        .iter()
+        .map(|name| material(name))
+        .collect::<Result<HashSet<Material>, ApiError>>()?;
+
+    let trees = rg.calculate_factory_requirements(targets, inputs.clone());
+    let reqs = rg.factory_requirements_from_trees(&trees, inputs);
+
+    Ok(RequirementsDocument::from(&reqs))
+}
+
+fn material(name: &str) -> Result<Material, ApiError> {
+    Material::parse_loose(name).map_err(|e| ApiError::new(400, e.to_string()))
+}
+
+fn path_material(segment: &str) -> Result<Material, ApiError> {
+    let name = percent_decode_str(segment)
+        .decode_utf8()
+        .map_err(|_| ApiError::new(400, "Material name isn't valid UTF-8"))?;
+
+    material(&name)
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<String, ApiError> {
+    serde_json::to_string(value).map_err(|e| ApiError::new(500, e.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::Value;
+
+    use super::*;
+
+    fn get(url: &str) -> (u16, Value) {
+        let response = handle(&ResourceGraph::default(), &Method::Get, url, "");
+
+        (
+            response.status,
+            serde_json::from_str(&response.body).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_materials() {
+        let (status, body) = get("/materials");
+
+        assert_eq!(status, 200);
+        assert_eq!(
+            body[0],
+            serde_json::json!({
+                "material": "BasicMaterials",
+                "name": "Basic Materials",
+                "cli_name": "basic-materials",
+                "aliases": ["bmats"],
+            })
+        );
+    }
+
+    #[test]
+    fn test_recipe_lookup() {
+        let (status, body) = get("/recipes/Shell%20150mm?verbose=1");
+        assert_eq!(status, 200);
+        assert!(!body.as_array().unwrap().is_empty());
+        assert_eq!(get("/recipes/150s").1, body);
+
+        let (status, body) = get("/uses/shell");
+        assert_eq!(status, 400);
+        assert!(body["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Unknown material 'shell', did you mean"));
+    }
+
+    #[test]
+    fn test_calculate() {
+        let rg = ResourceGraph::default();
+        let request = CalculateRequest {
+            targets: vec![("pipe".to_string(), 100), ("Pipe".to_string(), 20)]
+                .into_iter()
+                .collect(),
+            inputs: vec!["comps".to_string()],
+        };
+
+        let document = calculate(&rg, &request).unwrap();
+        assert_eq!(document.targets[&Material::Pipe], 120.0);
+        assert!(document.inputs.contains_key(&Material::Components));
+
+        let empty = CalculateRequest {
+            targets: BTreeMap::new(),
+            inputs: Vec::new(),
+        };
+        assert_eq!(calculate(&rg, &empty).unwrap_err().status, 400);
+    }
+
+    #[test]
+    fn test_calculate_rate_overflow() {
+        let rg = ResourceGraph::default();
+        let request = CalculateRequest {
+            targets: vec![("pipe".to_string(), u64::MAX), ("Pipe".to_string(), 1)]
+                .into_iter()
+                .collect(),
+            inputs: Vec::new(),
+        };
+
+        assert_eq!(
+            calculate(&rg, &request).unwrap_err(),
+            ApiError::new(400, "Total rate for Pipe is too large")
+        );
+    }
+
+    #[test]
+    fn test_unknown_routes() {
+        let rg = ResourceGraph::default();
+
+        assert_eq!(get("/recipes").0, 404);
+        assert_eq!(handle(&rg, &Method::Get, "/calculate", "").status, 405);
+        assert_eq!(handle(&rg, &Method::Post, "/calculate", "{").status, 400);
+    }
+}
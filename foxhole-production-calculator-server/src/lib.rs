@@ -0,0 +1,166 @@
+//! HTTP API for the foxhole production calculator, serving JSON over the bundled structure data.
+
+use std::io::Read;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::thread;
+
+use foxhole_production_calculator::ResourceGraph;
+use tiny_http::{Header, Request, Response};
+
+use crate::api::{ApiError, ApiResponse};
+
+pub mod api;
+
+/// Largest request body read, in bytes. Anything bigger is turned away unread.
+pub const MAX_BODY_SIZE: u64 = 64 * 1024;
+
+pub struct Server {
+    http: tiny_http::Server,
+    rg: ResourceGraph,
+}
+
+impl Server {
+    /// Listen on `addr`. Port 0 picks a free port, see [`Server::local_addr`].
+    pub fn bind<A: ToSocketAddrs>(
+        addr: A,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        Ok(Self {
+            http: tiny_http::Server::http(addr)?,
+            rg: ResourceGraph::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.http
+            .server_addr()
+            .to_ip()
+            .expect("Server should listen on TCP")
+    }
+
+    /// Answer requests forever, each on its own thread. tiny_http doesn't expose the socket to
+    /// set a read timeout on, so a client that stalls while sending its body only holds up its
+    /// own thread until it disconnects.
+    pub fn run(&self) {
+        thread::scope(|scope| {
+            for request in self.http.incoming_requests() {
+                scope.spawn(|| self.respond(request));
+            }
+        });
+    }
+
+    fn respond(&self, mut request: Request) {
+        let mut body = String::new();
+        // Read one byte past the limit, to tell a body at the limit from one over it.
+        let read = request
+            .as_reader()
+            .take(MAX_BODY_SIZE + 1)
+            .read_to_string(&mut body);
+        let response = match read {
+            Ok(size) if size as u64 > MAX_BODY_SIZE => ApiResponse::from(ApiError::new(
+                413,
+                format!("Request body is over {} bytes", MAX_BODY_SIZE),
+            )),
+            Ok(_) => api::handle(&self.rg, request.method(), request.url(), &body),
+            Err(e) => ApiResponse::from(ApiError::new(400, e.to_string())),
+        };
+
+        let content_type = Header::from_bytes("Content-Type", "application/json")
+            .expect("Content type header should be valid");
+        let http_response = Response::from_string(response.body)
+            .with_status_code(response.status)
+            .with_header(content_type);
+        if let Err(e) = request.respond(http_response) {
+            eprintln!("Failed to send response: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn spawn_server() -> String {
+        let server = Server::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.local_addr());
+        thread::spawn(move || server.run());
+
+        url
+    }
+
+    #[test]
+    fn test_calculate_over_http() {
+        let url = spawn_server();
+
+        let response = ureq::post(&format!("{}/calculate", url))
+            .send_json(json!({"targets": {"bmats": 100}}))
+            .unwrap();
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+
+        let body: Value = response.into_json().unwrap();
+        assert_eq!(body["schema_version"], 1);
+        assert_eq!(body["targets"]["BasicMaterials"], 100.0);
+    }
+
+    #[test]
+    fn test_errors_over_http() {
+        let url = spawn_server();
+
+        match ureq::get(&format!("{}/recipes/rmats", url)).call() {
+            Err(ureq::Error::Status(status, response)) => {
+                assert_eq!(status, 400);
+                let body: Value = response.into_json().unwrap();
+                assert!(body["error"]
+                    .as_str()
+                    .unwrap()
+                    .contains("Refined Materials"));
+            }
+            other => panic!("Expected an error status, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_body_size_limit() {
+        let url = spawn_server();
+
+        // Padding after the JSON keeps the body valid, so only its size can fail it.
+        let mut at_limit = r#"{"targets": {"bmats": 100}}"#.to_string();
+        at_limit.push_str(&" ".repeat(MAX_BODY_SIZE as usize - at_limit.len()));
+        let response = ureq::post(&format!("{}/calculate", url))
+            .send_string(&at_limit)
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        match ureq::post(&format!("{}/calculate", url)).send_string(&format!("{} ", at_limit)) {
+            Err(ureq::Error::Status(status, _)) => assert_eq!(status, 413),
+            other => panic!("Expected an error status, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_stalled_client() {
+        let url = spawn_server();
+
+        // Promise a body and never send it. tiny_http reads bodies of up to 1KiB itself before
+        // handing the request over, so the promised body has to be bigger to stall the server.
+        let mut stalled = TcpStream::connect(url.trim_start_matches("http://")).unwrap();
+        stalled
+            .write_all(
+                b"POST /calculate HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4096\r\n\r\n{",
+            )
+            .unwrap();
+        stalled.flush().unwrap();
+
+        let response = ureq::post(&format!("{}/calculate", url))
+            .timeout(Duration::from_secs(10))
+            .send_json(json!({"targets": {"bmats": 100}}))
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        drop(stalled);
+    }
+}
@@ -0,0 +1,28 @@
+use std::process;
+
+use clap::Parser;
+use foxhole_production_calculator_server::Server;
+
+/// HTTP API for calculating factory requirements and looking up the structure data.
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Address to listen on.
+    #[clap(short, long, value_parser, default_value = "127.0.0.1:8080")]
+    address: String,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let server = match Server::bind(&args.address) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Could not listen on {}: {}", args.address, e);
+            process::exit(1);
+        }
+    };
+    eprintln!("Listening on http://{}", server.local_addr());
+
+    server.run();
+}
@@ -10,6 +10,10 @@ use genco::{
 use serde::{Deserialize, Serialize};
 use strum::{Display, EnumIter, EnumString};
 
+pub mod parse;
+
+pub use parse::ParseMaterialError;
+
 #[derive(
     Debug,
     Serialize,
@@ -74,6 +78,20 @@ pub enum Material {
     DamagedComponents,
 }
 
+impl Material {
+    /// Whether the material is moved through pipes rather than carried.
+    pub fn is_liquid(&self) -> bool {
+        matches!(
+            self,
+            Material::Oil
+                | Material::Water
+                | Material::HeavyOil
+                | Material::EnrichedOil
+                | Material::Petrol
+        )
+    }
+}
+
 impl<L> FormatInto<L> for Material
 where
     L: Lang,
@@ -277,15 +295,50 @@ where
     }
 }
 
+/// Ground space taken up by a structure, in meters.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Footprint {
+    pub width: u32,
+    pub length: u32,
+}
+
+impl Footprint {
+    pub fn new(width: u32, length: u32) -> Self {
+        Self { width, length }
+    }
+}
+
+impl<L> FormatInto<L> for Footprint
+where
+    L: Lang,
+{
+    fn format_into(self, tokens: &mut Tokens<L>) {
+        let Self { width, length } = self;
+
+        quote_in! { *tokens =>
+            Footprint {
+                width: $width,
+                length: $length,
+            }
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Structure {
+    pub footprint: Footprint,
     pub default_upgrade: Upgrade,
     pub upgrades: HashMap<String, Upgrade>,
 }
 
 impl Structure {
-    pub fn new(default_upgrade: Upgrade, upgrades: HashMap<String, Upgrade>) -> Self {
+    pub fn new(
+        footprint: Footprint,
+        default_upgrade: Upgrade,
+        upgrades: HashMap<String, Upgrade>,
+    ) -> Self {
         Self {
+            footprint,
             default_upgrade,
             upgrades,
         }
@@ -297,6 +350,7 @@ where
     L: Lang,
 {
     fn format_into(self, tokens: &mut Tokens<L>) {
+        let footprint = self.footprint;
         let default_upgrade = self.default_upgrade;
         // let upgrades: Vec<(String, Upgrade)> = self.upgrades.into_iter().collect();
 
@@ -311,6 +365,7 @@ where
 
         quote_in! { *tokens =>
             Structure {
+                footprint: $footprint,
                 default_upgrade: $default_upgrade,
                 upgrades: $upgrade_tokens
             }
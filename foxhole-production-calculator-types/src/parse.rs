@@ -0,0 +1,261 @@
+//! Forgiving material names: display names, variant names and CLI names in any case, community
+//! shorthand, and small typos.
+
+use std::fmt;
+
+use clap::ValueEnum;
+use strum::IntoEnumIterator;
+
+use crate::Material;
+
+/// Shorthand used by players, matched the same way as full names.
+pub const ALIASES: &[(&str, Material)] = &[
+    ("bmats", Material::BasicMaterials),
+    ("cmats", Material::ConstructionMaterials),
+    ("pcmats", Material::ProcessedConstructionMaterials),
+    ("scmats", Material::SteelConstructionMaterials),
+    ("emats", Material::ExplosiveMaterials),
+    ("hemats", Material::HeavyExplosiveMaterials),
+    ("comps", Material::Components),
+    ("dcomps", Material::DamagedComponents),
+    ("concrete", Material::ConcreteMaterials),
+    ("amats1", Material::AssemblyMaterialsI),
+    ("amats2", Material::AssemblyMaterialsII),
+    ("amats3", Material::AssemblyMaterialsIII),
+    ("amats4", Material::AssemblyMaterialsIV),
+    ("amats5", Material::AssemblyMaterialsV),
+    ("sandbags", Material::SandBag),
+    ("3c", Material::Rocket3CHighExplosive),
+    ("4c", Material::Rocket4CFire),
+    ("75s", Material::Shell75MM),
+    ("94.5s", Material::Shell945MM),
+    ("120s", Material::Shell120MM),
+    ("150s", Material::Shell150MM),
+    ("250s", Material::Shell250MM),
+    ("300s", Material::Shell300MM),
+];
+
+/// Shorthand for materials that exist in game but aren't part of the structure data.
+const UNSUPPORTED: &[(&str, &str)] = &[("rmats", "Refined Materials")];
+
+/// Most suggestions listed in a [`ParseMaterialError::Unknown`].
+const MAX_SUGGESTIONS: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseMaterialError {
+    /// Nothing matched closely enough. Suggestions are ordered best first.
+    Unknown {
+        input: String,
+        suggestions: Vec<Material>,
+    },
+    /// A known name for a material the calculator doesn't model.
+    Unsupported { input: String, name: &'static str },
+}
+
+impl fmt::Display for ParseMaterialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseMaterialError::Unknown { input, suggestions } => {
+                write!(f, "Unknown material '{}'", input)?;
+                if let Some((last, rest)) = suggestions.split_last() {
+                    let rest: Vec<String> = rest.iter().map(|m| m.to_string()).collect();
+                    if rest.is_empty() {
+                        write!(f, ", did you mean {}?", last)?;
+                    } else {
+                        write!(f, ", did you mean {} or {}?", rest.join(", "), last)?;
+                    }
+                }
+
+                Ok(())
+            }
+            ParseMaterialError::Unsupported { input, name } => write!(
+                f,
+                "'{}' is {}, which isn't produced by any structure the calculator knows about",
+                input, name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseMaterialError {}
+
+impl Material {
+    /// Parse a material name the way a player would type it.
+    ///
+    /// Case, spaces and punctuation are ignored. An exact match against a name or alias wins,
+    /// then a unique prefix, then a unique closest name within a typo or two.
+    pub fn parse_loose(input: &str) -> Result<Material, ParseMaterialError> {
+        let key = normalize(input);
+        let names = names();
+
+        if let Some((_, material)) = names.iter().find(|(name, _)| *name == key) {
+            return Ok(*material);
+        }
+        if let Some((_, name)) = UNSUPPORTED
+            .iter()
+            .find(|(alias, _)| normalize(alias) == key)
+        {
+            return Err(ParseMaterialError::Unsupported {
+                input: input.to_string(),
+                name,
+            });
+        }
+
+        // Distance of every material to the input, with prefixes counting as exact.
+        let mut scored: Vec<(usize, Material)> = Vec::new();
+        for (name, material) in &names {
+            let distance = if key.len() >= 3 && name.starts_with(&key) {
+                0
+            } else {
+                edit_distance(&key, name)
+            };
+            match scored.iter_mut().find(|(_, m)| m == material) {
+                Some(entry) => entry.0 = entry.0.min(distance),
+                None => scored.push((distance, *material)),
+            }
+        }
+        scored.sort();
+
+        let close = if key.len() <= 5 { 1 } else { 2 };
+        match scored.as_slice() {
+            [(best, material), (next, _), ..] if *best <= close && next > best => {
+                return Ok(*material)
+            }
+            [(best, material)] if *best <= close => return Ok(*material),
+            _ => {}
+        }
+
+        let loose = close.max(key.len() / 2);
+        Err(ParseMaterialError::Unknown {
+            input: input.to_string(),
+            suggestions: scored
+                .into_iter()
+                .filter(|(distance, _)| *distance <= loose)
+                .take(MAX_SUGGESTIONS)
+                .map(|(_, material)| material)
+                .collect(),
+        })
+    }
+    /// Name to give for the material on the command line.
+    pub fn cli_name(&self) -> String {
+        self.to_possible_value()
+            .expect("Materials should all be selectable")
+            .get_name()
+            .to_string()
+    }
+
+    /// Shorthand players use for the material.
+    pub fn aliases(&self) -> Vec<&'static str> {
+        ALIASES
+            .iter()
+            .filter(|(_, aliased)| aliased == self)
+            .map(|(alias, _)| *alias)
+            .collect()
+    }
+}
+
+/// Lowercase ASCII letters and digits only, so "Shell 94.5mm" and "shell-945mm" compare equal.
+pub fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Every normalized name a material goes by.
+fn names() -> Vec<(String, Material)> {
+    let mut names = Vec::new();
+    for material in Material::iter() {
+        names.push((normalize(&material.to_string()), material));
+        names.push((normalize(&format!("{:?}", material)), material));
+        if let Some(value) = material.to_possible_value() {
+            names.push((normalize(value.get_name()), material));
+        }
+    }
+    for (alias, material) in ALIASES {
+        names.push((normalize(alias), *material));
+    }
+
+    names
+}
+
+/// Levenshtein distance.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_names_and_aliases() {
+        assert_eq!(
+            Material::parse_loose("Basic Materials"),
+            Ok(Material::BasicMaterials)
+        );
+        assert_eq!(
+            Material::parse_loose("heavy-explosive-materials"),
+            Ok(Material::HeavyExplosiveMaterials)
+        );
+        assert_eq!(
+            Material::parse_loose("shell 94.5MM"),
+            Ok(Material::Shell945MM)
+        );
+        assert_eq!(Material::parse_loose("BMATS"), Ok(Material::BasicMaterials));
+        assert_eq!(Material::parse_loose("150s"), Ok(Material::Shell150MM));
+        assert_eq!(
+            Material::parse_loose("rmats"),
+            Err(ParseMaterialError::Unsupported {
+                input: "rmats".to_string(),
+                name: "Refined Materials"
+            })
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert_eq!(Material::parse_loose("petr"), Ok(Material::Petrol));
+        assert_eq!(Material::parse_loose("componets"), Ok(Material::Components));
+        assert_eq!(
+            Material::parse_loose("hemat"),
+            Ok(Material::HeavyExplosiveMaterials)
+        );
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let err = Material::parse_loose("shell").unwrap_err();
+
+        assert_eq!(
+            err,
+            ParseMaterialError::Unknown {
+                input: "shell".to_string(),
+                suggestions: vec![
+                    Material::Shell75MM,
+                    Material::Shell945MM,
+                    Material::Shell120MM
+                ],
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Unknown material 'shell', did you mean Shell 75mm, Shell 94.5mm or Shell 120mm?"
+        );
+        assert_eq!(
+            Material::parse_loose("zzzzzzzz").unwrap_err().to_string(),
+            "Unknown material 'zzzzzzzz'"
+        );
+    }
+}
@@ -0,0 +1,431 @@
+//! JavaScript bindings for the foxhole production calculator.
+//!
+//! Everything crossing into JavaScript is converted with serde into plain objects, typed by the
+//! definitions in `types.d.ts`. Material names in requests are matched loosely, as on the
+//! command line.
+
+use std::collections::{BTreeMap, HashSet};
+use std::num::NonZeroUsize;
+
+use foxhole_production_calculator::catalog::{self, Recipe};
+use foxhole_production_calculator::document::RequirementsDocument;
+use foxhole_production_calculator::preferences::RecipePreferences;
+use foxhole_production_calculator::{ResourceGraph, StructureTree};
+use foxhole_production_calculator_types::Material;
+use indextree::NodeId;
+use serde::{Deserialize, Serialize};
+use serde_wasm_bindgen::Serializer;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+/// TypeScript definitions for the objects passed to and from JavaScript. The custom section copies
+/// it while expanding, so it's otherwise unused outside of tests.
+#[allow(dead_code)]
+const TYPESCRIPT: &str = include_str!("../types.d.ts");
+
+#[wasm_bindgen(typescript_custom_section)]
+const TYPES: &'static str = TYPESCRIPT;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "CalculateRequest")]
+    pub type JsCalculateRequest;
+    #[wasm_bindgen(typescript_type = "Requirements")]
+    pub type JsRequirements;
+    #[wasm_bindgen(typescript_type = "ChainStep[]")]
+    pub type JsChain;
+    #[wasm_bindgen(typescript_type = "MaterialInfo[]")]
+    pub type JsMaterials;
+    #[wasm_bindgen(typescript_type = "StructureInfo[]")]
+    pub type JsStructures;
+    #[wasm_bindgen(typescript_type = "Recipe[]")]
+    pub type JsRecipes;
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CalculateRequest {
+    /// [Unit/Hour]
+    pub targets: BTreeMap<String, u64>,
+    #[serde(default)]
+    pub inputs: Vec<String>,
+    #[serde(default)]
+    pub preferences: RecipePreferences,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alternative {
+    pub node: usize,
+    pub building: String,
+    pub upgrade: Option<String>,
+    pub channel: usize,
+    pub count: f32,
+    pub active: bool,
+}
+
+/// An active step of a production chain.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChainStep {
+    pub tree: usize,
+    pub node: usize,
+    pub parent: Option<usize>,
+    pub depth: usize,
+    pub building: String,
+    pub upgrade: Option<String>,
+    pub channel: usize,
+    pub count: f32,
+    pub material: Material,
+    /// [Unit/Hour]
+    pub rate: f32,
+    /// [MW]
+    pub power: f32,
+    /// Every way of making this step's material, including this one.
+    pub alternatives: Vec<Alternative>,
+}
+
+/// A calculator holding the trees of its last calculation, so alternatives can be switched.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct Calculator {
+    rg: ResourceGraph,
+    trees: Vec<StructureTree>,
+    user_inputs: HashSet<Material>,
+}
+
+#[wasm_bindgen]
+impl Calculator {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Calculator {
+        Calculator::default()
+    }
+
+    /// Calculate the requirements for a request, replacing any earlier calculation.
+    pub fn calculate(&mut self, request: JsCalculateRequest) -> Result<JsRequirements, JsError> {
+        let request: CalculateRequest = serde_wasm_bindgen::from_value(request.into())?;
+        let document = self
+            .calculate_request(&request)
+            .map_err(|e| JsError::new(&e))?;
+
+        to_js(&document)
+    }
+
+    /// Requirements of the current calculation.
+    pub fn requirements(&self) -> Result<JsRequirements, JsError> {
+        to_js(&self.document())
+    }
+
+    /// Active steps of every tree, each followed by the steps supplying it.
+    pub fn chain(&self) -> Result<JsChain, JsError> {
+        to_js(&self.chain_steps())
+    }
+
+    /// Switch a step to one of its alternatives, and return the new requirements. Throws for
+    /// nodes below an inactive alternative, which aren't part of the chain.
+    #[wasm_bindgen(js_name = selectAlternative)]
+    pub fn select_alternative(
+        &mut self,
+        tree: usize,
+        node: usize,
+    ) -> Result<JsRequirements, JsError> {
+        let document = self.select(tree, node).map_err(|e| JsError::new(&e))?;
+
+        to_js(&document)
+    }
+
+    pub fn materials() -> Result<JsMaterials, JsError> {
+        to_js(&catalog::material_infos())
+    }
+
+    pub fn structures(&self) -> Result<JsStructures, JsError> {
+        to_js(&self.rg.structures())
+    }
+
+    /// Every production channel making a material.
+    pub fn recipes(&self, material: &str) -> Result<JsRecipes, JsError> {
+        to_js(&self.lookup(material, ResourceGraph::recipes)?)
+    }
+
+    /// Every production channel consuming a material.
+    pub fn uses(&self, material: &str) -> Result<JsRecipes, JsError> {
+        to_js(&self.lookup(material, ResourceGraph::uses)?)
+    }
+}
+
+impl Calculator {
+    pub fn calculate_request(
+        &mut self,
+        request: &CalculateRequest,
+    ) -> Result<RequirementsDocument, String> {
+        if request.targets.is_empty() {
+            return Err("At least one target is needed".to_string());
+        }
+
+        let targets = request
+            .targets
+            .iter()
+            .map(|(name, rate)| Ok((parse(name)?, *rate)))
+            .collect::<Result<Vec<(Material, u64)>, String>>()?;
+        let targets = catalog::sum_targets(targets).map_err(|e| e.to_string())?;
+        let user_inputs = request
+            .inputs
+            .iter()
+            .map(|name| parse(name))
+            .collect::<Result<HashSet<Material>, String>>()?;
+
+        self.rg = ResourceGraph::default().with_preferences(request.preferences.clone());
+        self.trees = self
+            .rg
+            .calculate_factory_requirements(targets, user_inputs.clone());
+        self.user_inputs = user_inputs;
+
+        Ok(self.document())
+    }
+
+    pub fn document(&self) -> RequirementsDocument {
+        let reqs = self
+            .rg
+            .factory_requirements_from_trees(&self.trees, self.user_inputs.clone());
+
+        RequirementsDocument::from(&reqs)
+    }
+
+    pub fn chain_steps(&self) -> Vec<ChainStep> {
+        let mut steps = Vec::new();
+        for (tree_idx, tree) in self.trees.iter().enumerate() {
+            let mut stack: Vec<NodeId> = tree.roots.iter().flatten().rev().copied().collect();
+            while let Some(node_id) = stack.pop() {
+                let details = self
+                    .rg
+                    .node_details(tree, node_id)
+                    .expect("Node should exist");
+                if !details.active {
+                    continue;
+                }
+
+                let alternatives = details
+                    .alternatives
+                    .iter()
+                    .map(|alt_id| {
+                        let alt = self
+                            .rg
+                            .node_details(tree, *alt_id)
+                            .expect("Node should exist");
+                        Alternative {
+                            node: (*alt_id).into(),
+                            building: alt.building,
+                            upgrade: alt.upgrade,
+                            channel: alt.channel,
+                            count: alt.count,
+                            active: alt.active,
+                        }
+                    })
+                    .collect();
+                steps.push(ChainStep {
+                    tree: tree_idx,
+                    node: node_id.into(),
+                    parent: details.parent.map(Into::into),
+                    depth: details.depth,
+                    building: details.building,
+                    upgrade: details.upgrade,
+                    channel: details.channel,
+                    count: details.count,
+                    material: details.output.material,
+                    rate: details.output.rate,
+                    power: details.power,
+                    alternatives,
+                });
+                stack.extend(details.children.into_iter().rev());
+            }
+        }
+
+        steps
+    }
+
+    pub fn select(&mut self, tree: usize, node: usize) -> Result<RequirementsDocument, String> {
+        let structure_tree = self
+            .trees
+            .get_mut(tree)
+            .ok_or_else(|| format!("No tree {}", tree))?;
+        let node_id = NonZeroUsize::new(node)
+            .and_then(|index| structure_tree.arena.get_node_id_at(index))
+            .ok_or_else(|| format!("No node {} in tree {}", node, tree))?;
+        // Steps below an inactive alternative aren't part of the chain, so switching them would
+        // change nothing shown.
+        let detached = node_id
+            .ancestors(&structure_tree.arena)
+            .skip(1)
+            .any(|ancestor| {
+                !structure_tree
+                    .get_node(ancestor)
+                    .expect("Node should exist")
+                    .is_active()
+            });
+        if detached {
+            return Err(format!(
+                "Node {} in tree {} is below an inactive alternative",
+                node, tree
+            ));
+        }
+        structure_tree.activate_node(node_id);
+
+        Ok(self.document())
+    }
+
+    fn lookup(
+        &self,
+        material: &str,
+        find: fn(&ResourceGraph, Material) -> Vec<Recipe>,
+    ) -> Result<Vec<Recipe>, JsError> {
+        let material = parse(material).map_err(|e| JsError::new(&e))?;
+
+        Ok(find(&self.rg, material))
+    }
+}
+
+fn parse(name: &str) -> Result<Material, String> {
+    Material::parse_loose(name).map_err(|e| e.to_string())
+}
+
+/// Convert to plain JavaScript objects, rather than `Map`s, typed as `T`.
+fn to_js<S: Serialize, T: JsCast>(value: &S) -> Result<T, JsError> {
+    let value = value.serialize(&Serializer::json_compatible())?;
+
+    Ok(value.unchecked_into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(json: &str) -> CalculateRequest {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_calculate_request() {
+        let mut calculator = Calculator::default();
+
+        let document = calculator
+            .calculate_request(&request(
+                r#"{"targets": {"cmats": 100}, "inputs": ["Salvage"]}"#,
+            ))
+            .unwrap();
+        assert_eq!(document.targets[&Material::ConstructionMaterials], 100.0);
+        assert!(document.inputs.contains_key(&Material::Salvage));
+
+        let err = calculator
+            .calculate_request(&request(r#"{"targets": {"shell": 1}}"#))
+            .unwrap_err();
+        assert!(err.starts_with("Unknown material 'shell'"));
+    }
+
+    #[test]
+    fn test_select_alternative() {
+        let mut calculator = Calculator::default();
+        calculator
+            .calculate_request(&request(r#"{"targets": {"coke": 100}}"#))
+            .unwrap();
+
+        let chain = calculator.chain_steps();
+        let step = chain
+            .iter()
+            .find(|step| step.alternatives.len() > 1)
+            .expect("Coke should have alternatives");
+        let other = step
+            .alternatives
+            .iter()
+            .find(|alt| !alt.active)
+            .unwrap()
+            .clone();
+
+        let document = calculator.select(step.tree, other.node).unwrap();
+        assert!(document.buildings.iter().any(
+            |building| building.building == other.building && building.upgrade == other.upgrade
+        ));
+        assert!(calculator
+            .chain_steps()
+            .iter()
+            .any(|step| step.node == other.node));
+        assert!(calculator.select(5, 0).is_err());
+        assert_eq!(
+            calculator.select(step.tree, 0),
+            Err(format!("No node 0 in tree {}", step.tree))
+        );
+        assert_eq!(
+            calculator.select(step.tree, usize::MAX),
+            Err(format!("No node {} in tree {}", usize::MAX, step.tree))
+        );
+    }
+
+    #[test]
+    fn test_select_below_inactive_alternative() {
+        let mut calculator = Calculator::default();
+        calculator
+            .calculate_request(&request(
+                r#"{"targets": {"pipe": 100}, "inputs": ["components"]}"#,
+            ))
+            .unwrap();
+
+        // Activate an alternative with steps of its own, then switch back to the original.
+        let (step, other, child) = calculator
+            .chain_steps()
+            .into_iter()
+            .filter(|step| step.alternatives.len() > 1)
+            .find_map(|step| {
+                let other = step.alternatives.iter().find(|alt| !alt.active)?.node;
+                calculator.select(step.tree, other).unwrap();
+                let child = calculator
+                    .chain_steps()
+                    .into_iter()
+                    .find(|child| child.parent == Some(other));
+                calculator.select(step.tree, step.node).unwrap();
+
+                Some((step, other, child?))
+            })
+            .expect("Some alternative should have inputs of its own");
+
+        assert_eq!(
+            calculator.select(child.tree, child.node),
+            Err(format!(
+                "Node {} in tree {} is below an inactive alternative",
+                child.node, child.tree
+            ))
+        );
+        assert!(calculator
+            .chain_steps()
+            .iter()
+            .all(|active| active.node != other && active.node != child.node));
+        assert!(calculator.chain_steps().contains(&step));
+    }
+
+    #[test]
+    fn test_calculate_without_targets() {
+        let mut calculator = Calculator::default();
+
+        assert_eq!(
+            calculator.calculate_request(&request(r#"{"targets": {}}"#)),
+            Err("At least one target is needed".to_string())
+        );
+    }
+
+    #[test]
+    fn test_calculate_rate_overflow() {
+        let mut calculator = Calculator::default();
+        let json = format!(r#"{{"targets": {{"pipe": {}, "Pipe": 1}}}}"#, u64::MAX);
+
+        assert_eq!(
+            calculator.calculate_request(&request(&json)),
+            Err("Total rate for Pipe is too large".to_string())
+        );
+    }
+
+    #[test]
+    fn test_typescript_materials() {
+        for info in catalog::material_infos() {
+            assert!(
+                TYPESCRIPT.contains(&format!("\"{:?}\"", info.material)),
+                "{:?} is missing from types.d.ts",
+                info.material
+            );
+        }
+    }
+}
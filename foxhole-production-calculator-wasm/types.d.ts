@@ -0,0 +1,140 @@
+/** Material as it appears in results. Requests also accept display names and shorthand. */
+export type Material =
+  | "BasicMaterials"
+  | "Salvage"
+  | "ConstructionMaterials"
+  | "ProcessedConstructionMaterials"
+  | "Oil"
+  | "Petrol"
+  | "Coal"
+  | "Coke"
+  | "ExplosiveMaterials"
+  | "HeavyExplosiveMaterials"
+  | "FlameAmmo"
+  | "Components"
+  | "Water"
+  | "HeavyOil"
+  | "EnrichedOil"
+  | "Sulfur"
+  | "SteelConstructionMaterials"
+  | "ConcreteMaterials"
+  | "Pipe"
+  | "AssemblyMaterialsI"
+  | "AssemblyMaterialsII"
+  | "AssemblyMaterialsIII"
+  | "AssemblyMaterialsIV"
+  | "AssemblyMaterialsV"
+  | "MetalBeam"
+  | "SandBag"
+  | "BarbedWire"
+  | "Rocket3CHighExplosive"
+  | "Rocket4CFire"
+  | "Shell75MM"
+  | "Shell945MM"
+  | "Shell120MM"
+  | "Shell150MM"
+  | "Shell250MM"
+  | "Shell300MM"
+  | "DamagedComponents";
+
+export type MaterialMap<T> = Partial<Record<Material, T>>;
+
+export interface UpgradeRef {
+  building: string;
+  upgrade?: string | null;
+}
+
+export interface ChannelRef extends UpgradeRef {
+  channel: number;
+}
+
+export interface RecipePreferences {
+  excluded_structures?: string[];
+  preferred?: MaterialMap<UpgradeRef>;
+  pinned?: MaterialMap<ChannelRef>;
+  excluded_upgrades?: UpgradeRef[];
+}
+
+export interface CalculateRequest {
+  /** Materials to produce, by any name. [Unit/Hour] */
+  targets: Record<string, number>;
+  /** Materials brought in from outside the factory, by any name. */
+  inputs?: string[];
+  preferences?: RecipePreferences;
+}
+
+export interface Requirements {
+  schema_version: number;
+  units: { rate: string; power: string; amount: string };
+  targets: MaterialMap<number>;
+  buildings: { building: string; upgrade: string | null; count: number }[];
+  channels: { building: string; upgrade: string | null; channel: number; count: number }[];
+  power: number;
+  build_cost: MaterialMap<number>;
+  inputs: MaterialMap<number>;
+  stockpile_usage: { material: Material; consumer: string | null; rate: number; amount: number }[];
+  balance: {
+    material: Material;
+    produced: number;
+    consumed: number;
+    imported: number;
+    exported: number;
+    surplus: number;
+  }[];
+}
+
+export interface Alternative {
+  node: number;
+  building: string;
+  upgrade: string | null;
+  channel: number;
+  count: number;
+  active: boolean;
+}
+
+/** An active step of a production chain. */
+export interface ChainStep {
+  tree: number;
+  node: number;
+  parent: number | null;
+  depth: number;
+  building: string;
+  upgrade: string | null;
+  channel: number;
+  count: number;
+  material: Material;
+  /** [Unit/Hour] */
+  rate: number;
+  /** [MW] */
+  power: number;
+  /** Every way of making this step's material, including this one. */
+  alternatives: Alternative[];
+}
+
+export interface MaterialInfo {
+  material: Material;
+  name: string;
+  /** Name accepted on the command line. */
+  cli_name: string;
+  aliases: string[];
+}
+
+export interface StructureInfo {
+  building: string;
+  build_cost: MaterialMap<number>;
+  upgrades: { name: string; build_cost: MaterialMap<number> }[];
+}
+
+export interface Flow {
+  material: Material;
+  rate: number;
+}
+
+export interface Recipe {
+  building: string;
+  upgrade: string | null;
+  channel: number;
+  power: number;
+  inputs: Flow[];
+  outputs: Flow[];
+}
@@ -42,6 +42,7 @@ fn main() {
 
     generate_named_structures(&mut tokens, &structures);
     generate_output_map(&mut tokens, &structures);
+    generate_input_map(&mut tokens, &structures);
     generate_structure_map(&mut tokens, &structures);
 
     // End lazy static scope
@@ -102,9 +103,43 @@ fn generate_output_map(tokens: &mut Tokens<lang::Rust>, structures: &HashMap<Str
 fn parse_upgrade(upgrade_map: &mut HashMap<Material, HashSet<Upgrade>>, upgrade: &Upgrade) {
     for production_channel in &upgrade.production_channels {
         for output in &production_channel.outputs {
-            let entry = upgrade_map
-                .entry(output.material)
-                .or_insert_with(HashSet::new);
+            let entry = upgrade_map.entry(output.material).or_default();
+
+            entry.insert(upgrade.clone());
+        }
+    }
+}
+
+fn generate_input_map(tokens: &mut Tokens<lang::Rust>, structures: &HashMap<String, Structure>) {
+    let hashmap = rust::import("std::collections", "HashMap");
+    let upgrade_import = rust::import("foxhole_production_calculator_types", "Upgrade");
+    quote_in! { *tokens =>
+        static ref INPUT_MAP: $hashmap<Material, Vec<$upgrade_import>> =$[' ']
+    };
+
+    let mut upgrade_map = HashMap::new();
+    for structure in structures.values() {
+        parse_upgrade_inputs(&mut upgrade_map, &structure.default_upgrade);
+        for upgrade in structure.upgrades.values() {
+            parse_upgrade_inputs(&mut upgrade_map, upgrade);
+        }
+    }
+
+    tokens.append(static_literal("vec!["));
+    tokens.push();
+    for (material, upgrades) in upgrade_map {
+        quote_in! { *tokens =>
+            ($material, vec![$(for upgrade in upgrades => $upgrade, $[' '])]),
+        }
+    }
+    tokens.append(static_literal("].into_iter().collect();"));
+    tokens.push();
+}
+
+fn parse_upgrade_inputs(upgrade_map: &mut HashMap<Material, HashSet<Upgrade>>, upgrade: &Upgrade) {
+    for production_channel in &upgrade.production_channels {
+        for input in &production_channel.inputs {
+            let entry = upgrade_map.entry(input.material).or_default();
 
             entry.insert(upgrade.clone());
         }
@@ -0,0 +1,460 @@
+//! Combining, scaling and comparing plans, either as factory requirements or straight from the
+//! trees they were worked out from.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use foxhole_production_calculator_types::Material;
+use serde::Serialize;
+
+use crate::balance::MaterialBalance;
+use crate::stockpile::{self, StockpileUsage};
+use crate::{
+    buildings_from_channels, FactoryRequirements, FactoryRequirementsChannel, ResourceGraph,
+    StructureTree,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArithmeticError {
+    /// Scale factors have to be finite and can't be negative.
+    InvalidFactor(f32),
+}
+
+impl fmt::Display for ArithmeticError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArithmeticError::InvalidFactor(factor) => write!(
+                f,
+                "Scale factor must be a finite number of at least 0, got {}",
+                factor
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ArithmeticError {}
+
+/// A value in two plans.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Change<T> {
+    pub before: T,
+    pub after: T,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BuildingChange {
+    pub building: String,
+    pub upgrade: Option<String>,
+    #[serde(flatten)]
+    pub count: Change<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MaterialChange<T> {
+    pub material: Material,
+    #[serde(flatten)]
+    pub value: Change<T>,
+}
+
+/// Differences between two plans. Only entries that changed are listed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RequirementsDiff {
+    pub buildings: Vec<BuildingChange>,
+    /// External inputs. [Unit/Hour]
+    pub inputs: Vec<MaterialChange<f32>>,
+    pub power: Change<f32>,
+    pub build_cost: Vec<MaterialChange<u64>>,
+}
+
+impl RequirementsDiff {
+    pub fn is_empty(&self) -> bool {
+        self.buildings.is_empty()
+            && self.inputs.is_empty()
+            && self.power.before == self.power.after
+            && self.build_cost.is_empty()
+    }
+}
+
+impl FactoryRequirements {
+    /// What changes going from this plan to `other`.
+    pub fn diff(&self, other: &FactoryRequirements) -> RequirementsDiff {
+        let building_counts = |reqs: &FactoryRequirements| -> BTreeMap<_, f32> {
+            reqs.buildings
+                .iter()
+                .map(|building| {
+                    (
+                        (building.building.clone(), building.upgrade.clone()),
+                        building.count,
+                    )
+                })
+                .collect()
+        };
+        let buildings = changes(&building_counts(self), &building_counts(other))
+            .into_iter()
+            .map(|((building, upgrade), count)| BuildingChange {
+                building,
+                upgrade,
+                count,
+            })
+            .collect();
+
+        let inputs = changes(&ordered(&self.inputs), &ordered(&other.inputs))
+            .into_iter()
+            .map(|(material, value)| MaterialChange { material, value })
+            .collect();
+        let build_cost = changes(&ordered(&self.build_cost), &ordered(&other.build_cost))
+            .into_iter()
+            .map(|(material, value)| MaterialChange { material, value })
+            .collect();
+
+        RequirementsDiff {
+            buildings,
+            inputs,
+            power: Change {
+                before: self.power,
+                after: other.power,
+            },
+            build_cost,
+        }
+    }
+}
+
+impl ResourceGraph {
+    /// Combine two plans into one. Buildings running the same channel are pooled before being
+    /// rounded up, so two plans that each need half a building share a single one.
+    pub fn merge_requirements(
+        &self,
+        a: &FactoryRequirements,
+        b: &FactoryRequirements,
+    ) -> FactoryRequirements {
+        let mut channels: BTreeMap<(String, Option<String>, usize), f32> = BTreeMap::new();
+        for channel in a.channels.iter().chain(&b.channels) {
+            *channels
+                .entry((
+                    channel.building.clone(),
+                    channel.upgrade.clone(),
+                    channel.channel,
+                ))
+                .or_default() += channel.count;
+        }
+        let channels = channels
+            .into_iter()
+            .map(
+                |((building, upgrade, channel), count)| FactoryRequirementsChannel {
+                    building,
+                    upgrade,
+                    channel,
+                    count,
+                },
+            )
+            .collect();
+
+        let mut inputs = a.inputs.clone();
+        for (material, rate) in &b.inputs {
+            *inputs.entry(*material).or_default() += rate;
+        }
+
+        let mut usage = HashMap::new();
+        for entry in a.stockpile_usage.iter().chain(&b.stockpile_usage) {
+            let (rate, amount) = usage
+                .entry((entry.material, entry.consumer.clone()))
+                .or_insert((0.0, 0.0));
+            *rate += entry.rate;
+            *amount += entry.amount;
+        }
+
+        let mut balance: BTreeMap<Material, [f32; 4]> = BTreeMap::new();
+        for row in a.balance.iter().chain(&b.balance) {
+            let totals = balance.entry(row.material).or_default();
+            totals[0] += row.produced;
+            totals[1] += row.consumed;
+            totals[2] += row.imported;
+            totals[3] += row.exported;
+        }
+        let balance = balance
+            .into_iter()
+            .map(|(material, [produced, consumed, imported, exported])| {
+                MaterialBalance::new(material, produced, consumed, imported, exported)
+            })
+            .collect();
+
+        self.requirements_from_parts(channels, inputs, stockpile::sorted_usage(usage), balance)
+    }
+
+    /// Multiply every count and rate in a plan by `factor`. Power and build cost are rounded up
+    /// again for the new counts, once per channel.
+    pub fn scale_requirements(
+        &self,
+        reqs: &FactoryRequirements,
+        factor: f32,
+    ) -> Result<FactoryRequirements, ArithmeticError> {
+        if !factor.is_finite() || factor < 0.0 {
+            return Err(ArithmeticError::InvalidFactor(factor));
+        }
+
+        let channels = reqs
+            .channels
+            .iter()
+            .map(|channel| FactoryRequirementsChannel {
+                count: channel.count * factor,
+                ..channel.clone()
+            })
+            .collect();
+        let inputs = reqs
+            .inputs
+            .iter()
+            .map(|(material, rate)| (*material, rate * factor))
+            .collect();
+        let stockpile_usage = reqs
+            .stockpile_usage
+            .iter()
+            .map(|usage| StockpileUsage {
+                rate: usage.rate * factor,
+                amount: usage.amount * factor,
+                ..usage.clone()
+            })
+            .collect();
+        let balance = reqs
+            .balance
+            .iter()
+            .map(|row| {
+                MaterialBalance::new(
+                    row.material,
+                    row.produced * factor,
+                    row.consumed * factor,
+                    row.imported * factor,
+                    row.exported * factor,
+                )
+            })
+            .collect();
+
+        Ok(self.requirements_from_parts(channels, inputs, stockpile_usage, balance))
+    }
+
+    /// Combine the plans worked out from two sets of trees, like [`Self::merge_requirements`].
+    pub fn merge_trees(
+        &self,
+        a: &[StructureTree],
+        b: &[StructureTree],
+        user_inputs: HashSet<Material>,
+    ) -> FactoryRequirements {
+        self.merge_requirements(
+            &self.factory_requirements_from_trees(a, user_inputs.clone()),
+            &self.factory_requirements_from_trees(b, user_inputs),
+        )
+    }
+
+    /// Scale the plan worked out from a set of trees, like [`Self::scale_requirements`].
+    pub fn scale_trees(
+        &self,
+        trees: &[StructureTree],
+        user_inputs: HashSet<Material>,
+        factor: f32,
+    ) -> Result<FactoryRequirements, ArithmeticError> {
+        self.scale_requirements(
+            &self.factory_requirements_from_trees(trees, user_inputs),
+            factor,
+        )
+    }
+
+    /// What changes going from the plan worked out from `before` to the one from `after`.
+    pub fn diff_trees(
+        &self,
+        before: &[StructureTree],
+        after: &[StructureTree],
+        user_inputs: HashSet<Material>,
+    ) -> RequirementsDiff {
+        self.factory_requirements_from_trees(before, user_inputs.clone())
+            .diff(&self.factory_requirements_from_trees(after, user_inputs))
+    }
+
+    fn requirements_from_parts(
+        &self,
+        channels: Vec<FactoryRequirementsChannel>,
+        inputs: HashMap<Material, f32>,
+        stockpile_usage: Vec<StockpileUsage>,
+        balance: Vec<MaterialBalance>,
+    ) -> FactoryRequirements {
+        let (power, build_cost) = self.channel_costs(&channels);
+
+        FactoryRequirements {
+            buildings: buildings_from_channels(&channels),
+            channels,
+            power,
+            build_cost,
+            inputs,
+            stockpile_usage,
+            balance,
+        }
+    }
+}
+
+fn ordered<T: Copy>(map: &HashMap<Material, T>) -> BTreeMap<Material, T> {
+    map.iter()
+        .map(|(material, value)| (*material, *value))
+        .collect()
+}
+
+/// Every key whose value differs between the maps. Missing keys count as the default value.
+fn changes<K, T>(before: &BTreeMap<K, T>, after: &BTreeMap<K, T>) -> Vec<(K, Change<T>)>
+where
+    K: Ord + Clone,
+    T: Copy + Default + PartialEq,
+{
+    let keys: BTreeSet<&K> = before.keys().chain(after.keys()).collect();
+
+    keys.into_iter()
+        .filter_map(|key| {
+            let change = Change {
+                before: before.get(key).copied().unwrap_or_default(),
+                after: after.get(key).copied().unwrap_or_default(),
+            };
+
+            (change.before != change.after).then(|| (key.clone(), change))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{build_structures, setup_test_structure_maps};
+
+    #[test]
+    fn test_merge_shares_rounding() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let concrete = vec![(Material::ConcreteMaterials, 1)].into_iter().collect();
+        let mut trees = rg.calculate_factory_requirements(concrete, HashSet::new());
+        let a = rg.factory_requirements_from_trees(&trees, HashSet::new());
+        let coke = vec![(Material::Coke, 1)].into_iter().collect();
+        trees.extend(rg.calculate_factory_requirements(coke, HashSet::new()));
+        let b = rg.factory_requirements_from_trees(&trees[1..], HashSet::new());
+
+        let merged = rg.merge_requirements(&a, &b);
+
+        // Half a Coke building in each plan becomes a single building.
+        assert_eq!(a.power + b.power, 3.0);
+        assert_eq!(merged.power, 2.0);
+        assert_eq!(merged.build_cost[&Material::BasicMaterials], 3);
+        assert_eq!(
+            merged,
+            rg.merge_trees(&trees[..1], &trees[1..], HashSet::new())
+        );
+
+        // Planned together, each tree still rounds its own half building up.
+        let together = rg.factory_requirements_from_trees(&trees, HashSet::new());
+        assert_eq!(together.power, 3.0);
+        assert_eq!(together.buildings, merged.buildings);
+        assert_eq!(together.channels, merged.channels);
+    }
+
+    #[test]
+    fn test_scale() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::Coke, 10)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+        let scaled = rg.scale_requirements(&reqs, 0.5).unwrap();
+
+        assert_eq!(scaled.buildings[0].count, 2.5);
+        assert_eq!(scaled.power, 3.0);
+        assert_eq!(scaled.build_cost[&Material::BasicMaterials], 6);
+        assert_eq!(scaled.inputs[&Material::Coal], 2.5);
+        assert_eq!(
+            scaled.balance[1],
+            MaterialBalance::new(Material::Coke, 5.0, 0.0, 0.0, 5.0)
+        );
+        assert_eq!(rg.scale_trees(&trees, HashSet::new(), 0.5).unwrap(), scaled);
+    }
+
+    #[test]
+    fn test_scale_rejects_invalid_factors() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::Coke, 10)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+
+        for factor in [-1.0, f32::NAN, f32::INFINITY] {
+            let err = rg.scale_requirements(&reqs, factor).unwrap_err();
+            assert!(matches!(err, ArithmeticError::InvalidFactor(_)));
+        }
+        assert_eq!(
+            ArithmeticError::InvalidFactor(-1.0).to_string(),
+            "Scale factor must be a finite number of at least 0, got -1"
+        );
+        let none = rg.scale_requirements(&reqs, 0.0).unwrap();
+        assert_eq!(none.power, 0.0);
+        assert_eq!(none.inputs[&Material::Coal], 0.0);
+    }
+
+    #[test]
+    fn test_diff() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::Coke, 10)].into_iter().collect();
+        let mut trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let before = rg.factory_requirements_from_trees(&trees, HashSet::new());
+        let slower = trees[0].roots.as_ref().unwrap()[1];
+        trees[0].activate_node(slower);
+        let after = rg.factory_requirements_from_trees(&trees, HashSet::new());
+
+        let diff = before.diff(&after);
+        assert_eq!(
+            rg.diff_trees(
+                &rg.calculate_factory_requirements(
+                    vec![(Material::Coke, 10)].into_iter().collect(),
+                    HashSet::new()
+                ),
+                &trees,
+                HashSet::new()
+            ),
+            diff
+        );
+        assert_eq!(
+            diff.buildings,
+            vec![
+                BuildingChange {
+                    building: "upgrade_a".to_string(),
+                    upgrade: None,
+                    count: Change {
+                        before: 0.0,
+                        after: 10.0,
+                    },
+                },
+                BuildingChange {
+                    building: "upgrade_a".to_string(),
+                    upgrade: Some("upgrade_a_1".to_string()),
+                    count: Change {
+                        before: 5.0,
+                        after: 0.0,
+                    },
+                },
+            ]
+        );
+        assert_eq!(
+            diff.inputs,
+            vec![MaterialChange {
+                material: Material::Coal,
+                value: Change {
+                    before: 5.0,
+                    after: 10.0,
+                },
+            }]
+        );
+        assert_eq!(diff.power.after - diff.power.before, 5.0);
+        // Ten of either building costs the same.
+        assert!(diff.build_cost.is_empty());
+        assert!(before.diff(&before).is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+//! Per material accounting of a plan.
+
+use std::collections::{BTreeMap, HashMap};
+
+use foxhole_production_calculator_types::Material;
+use serde::Serialize;
+
+use crate::stockpile::StockpileUsage;
+use crate::{ResourceGraph, StructureTree};
+
+/// Hourly flow of a single material through a plan. All rates are [Unit/Hour].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MaterialBalance {
+    pub material: Material,
+    /// Made by the plan's buildings, including byproducts.
+    pub produced: f32,
+    /// Used up by the plan's buildings.
+    pub consumed: f32,
+    /// Brought in from outside the factory or taken from a stockpile.
+    pub imported: f32,
+    /// Leaving the factory as one of the plan's targets.
+    pub exported: f32,
+    /// Left over once everything else is accounted for. Negative for a shortfall.
+    pub surplus: f32,
+}
+
+impl MaterialBalance {
+    pub fn new(
+        material: Material,
+        produced: f32,
+        consumed: f32,
+        imported: f32,
+        exported: f32,
+    ) -> Self {
+        Self {
+            material,
+            produced,
+            consumed,
+            imported,
+            exported,
+            surplus: produced + imported - consumed - exported,
+        }
+    }
+}
+
+impl ResourceGraph {
+    /// One row for every material produced, consumed, imported or exported by the active nodes.
+    /// Materials that only show up at a rate of zero, such as those below a zero rate target,
+    /// are left out.
+    pub(crate) fn material_balance(
+        &self,
+        trees: &[StructureTree],
+        inputs: &HashMap<Material, f32>,
+        stockpile_usage: &[StockpileUsage],
+    ) -> Vec<MaterialBalance> {
+        // (produced, consumed, imported, exported)
+        let mut rows: BTreeMap<Material, (f32, f32, f32, f32)> = BTreeMap::new();
+
+        for tree in trees {
+            if let Some(target) = tree.target() {
+                rows.entry(target.material).or_default().3 += target.value as f32;
+            }
+
+            for node in tree.traverse() {
+                let production_channel = self.production_channel(&node.structure);
+                for output in &production_channel.outputs {
+                    rows.entry(output.material).or_default().0 +=
+                        production_channel.hourly_rate(output.value) * node.count;
+                }
+                for input in &production_channel.inputs {
+                    rows.entry(input.material).or_default().1 +=
+                        production_channel.hourly_rate(input.value) * node.count;
+                }
+            }
+        }
+
+        for (material, rate) in inputs {
+            rows.entry(*material).or_default().2 += rate;
+        }
+        for usage in stockpile_usage {
+            rows.entry(usage.material).or_default().2 += usage.rate;
+        }
+
+        rows.into_iter()
+            .filter(|(_, (produced, consumed, imported, exported))| {
+                [produced, consumed, imported, exported]
+                    .iter()
+                    .any(|rate| **rate != 0.0)
+            })
+            .map(|(material, (produced, consumed, imported, exported))| {
+                MaterialBalance::new(material, produced, consumed, imported, exported)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::stockpile::Stockpile;
+    use crate::test::{build_structures, setup_test_structure_maps};
+
+    #[test]
+    fn test_balance_intermediate() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::ConcreteMaterials, 2), (Material::Coke, 4)]
+            .into_iter()
+            .collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+
+        assert_eq!(
+            reqs.balance,
+            vec![
+                MaterialBalance::new(Material::Coal, 0.0, 3.0, 3.0, 0.0),
+                MaterialBalance::new(Material::Coke, 6.0, 2.0, 0.0, 4.0),
+                MaterialBalance::new(Material::ConcreteMaterials, 2.0, 0.0, 0.0, 2.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_balance_stockpile_imports() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let stockpile = Stockpile::new(vec![(Material::Coke, 10)].into_iter().collect(), 1.0);
+        let outputs = vec![(Material::ConcreteMaterials, 12)]
+            .into_iter()
+            .collect();
+        let trees =
+            rg.calculate_factory_requirements_with_stockpile(outputs, HashSet::new(), &stockpile);
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+
+        let coke = reqs
+            .balance
+            .iter()
+            .find(|row| row.material == Material::Coke)
+            .unwrap();
+        assert_eq!(
+            coke,
+            &MaterialBalance::new(Material::Coke, 2.0, 12.0, 10.0, 0.0)
+        );
+        assert_eq!(coke.surplus, 0.0);
+    }
+
+    #[test]
+    fn test_balance_zero_rate() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::Coke, 0), (Material::Rocket4CFire, 1)]
+            .into_iter()
+            .collect();
+        let inputs: HashSet<Material> = vec![Material::Components].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, inputs.clone());
+        let reqs = rg.factory_requirements_from_trees(&trees, inputs);
+
+        // Nothing flows below the zero rate Coke target, so neither Coke nor Coal get a row.
+        assert_eq!(
+            reqs.balance,
+            vec![
+                MaterialBalance::new(Material::Components, 0.0, 1.0, 1.0, 0.0),
+                MaterialBalance::new(Material::Rocket4CFire, 1.0, 0.0, 0.0, 1.0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,315 @@
+//! Lookups into the structure data: what can be built, what makes a material and what uses it,
+//! along with the materials and targets every front end accepts.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use foxhole_production_calculator_types::{Material, ProductionChannel, Upgrade};
+use serde::Serialize;
+use strum::IntoEnumIterator;
+
+use crate::details::Flow;
+use crate::ResourceGraph;
+
+/// A single production channel of a building.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Recipe {
+    pub building: String,
+    pub upgrade: Option<String>,
+    /// Index of the production channel within the upgrade.
+    pub channel: usize,
+    /// [MW]
+    pub power: f32,
+    /// For a single building. [Unit/Hour]
+    pub inputs: Vec<Flow>,
+    /// For a single building. [Unit/Hour]
+    pub outputs: Vec<Flow>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UpgradeInfo {
+    pub name: String,
+    /// Cost on top of the base structure.
+    pub build_cost: BTreeMap<Material, u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StructureInfo {
+    pub building: String,
+    pub build_cost: BTreeMap<Material, u64>,
+    pub upgrades: Vec<UpgradeInfo>,
+}
+
+/// A material and the names it can be asked for by.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MaterialInfo {
+    pub material: Material,
+    pub name: String,
+    pub cli_name: String,
+    pub aliases: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetError {
+    /// The rates asked of a material add up to more than fits in a `u64`.
+    RateTooLarge(Material),
+}
+
+impl fmt::Display for TargetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetError::RateTooLarge(material) => {
+                write!(f, "Total rate for {} is too large", material)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TargetError {}
+
+/// Every material, in declaration order.
+pub fn material_infos() -> Vec<MaterialInfo> {
+    Material::iter()
+        .map(|material| MaterialInfo {
+            material,
+            name: material.to_string(),
+            cli_name: material.cli_name(),
+            aliases: material.aliases(),
+        })
+        .collect()
+}
+
+/// Add up the rates of targets, some of which may ask for the same material. [Unit/Hour]
+pub fn sum_targets<I>(targets: I) -> Result<HashMap<Material, u64>, TargetError>
+where
+    I: IntoIterator<Item = (Material, u64)>,
+{
+    let mut totals: HashMap<Material, u64> = HashMap::new();
+    for (material, rate) in targets {
+        let total = totals.entry(material).or_default();
+        *total = total
+            .checked_add(rate)
+            .ok_or(TargetError::RateTooLarge(material))?;
+    }
+
+    Ok(totals)
+}
+
+impl ResourceGraph {
+    /// Every structure and its upgrades, in name order.
+    pub fn structures(&self) -> Vec<StructureInfo> {
+        let mut structures: Vec<StructureInfo> = self
+            .catalog
+            .structure_map
+            .values()
+            .map(|structure| {
+                let mut upgrades: Vec<UpgradeInfo> = structure
+                    .upgrades
+                    .values()
+                    .map(|upgrade| UpgradeInfo {
+                        name: upgrade.name.clone(),
+                        build_cost: build_cost(upgrade),
+                    })
+                    .collect();
+                upgrades.sort_by(|a, b| a.name.cmp(&b.name));
+
+                StructureInfo {
+                    building: structure.default_upgrade.name.clone(),
+                    build_cost: build_cost(&structure.default_upgrade),
+                    upgrades,
+                }
+            })
+            .collect();
+        structures.sort_by(|a, b| a.building.cmp(&b.building));
+
+        structures
+    }
+
+    /// Every production channel with the material as an output, whether or not the graph's
+    /// preferences allow it.
+    pub fn recipes(&self, material: Material) -> Vec<Recipe> {
+        recipes(self.catalog.upgrade_map.get(&material), |channel| {
+            channel
+                .outputs
+                .iter()
+                .any(|output| output.material == material)
+        })
+    }
+
+    /// Every production channel with the material as an input.
+    pub fn uses(&self, material: Material) -> Vec<Recipe> {
+        recipes(self.catalog.consumer_map.get(&material), |channel| {
+            channel
+                .inputs
+                .iter()
+                .any(|input| input.material == material)
+        })
+    }
+}
+
+fn build_cost(upgrade: &Upgrade) -> BTreeMap<Material, u64> {
+    upgrade
+        .build_costs
+        .iter()
+        .map(|cost| (cost.material, cost.cost))
+        .collect()
+}
+
+/// Channels of the upgrades matching `filter`, ordered by building, upgrade and channel.
+fn recipes<F>(upgrades: Option<&Vec<Upgrade>>, filter: F) -> Vec<Recipe>
+where
+    F: Fn(&ProductionChannel) -> bool,
+{
+    let mut recipes = Vec::new();
+    for upgrade in upgrades.into_iter().flatten() {
+        let (building, upgrade_name) = match &upgrade.parent {
+            Some(parent) => (parent.clone(), Some(upgrade.name.clone())),
+            None => (upgrade.name.clone(), None),
+        };
+
+        for (channel, production_channel) in upgrade.production_channels.iter().enumerate() {
+            if !filter(production_channel) {
+                continue;
+            }
+
+            recipes.push(Recipe {
+                building: building.clone(),
+                upgrade: upgrade_name.clone(),
+                channel,
+                power: production_channel.power,
+                inputs: production_channel
+                    .inputs
+                    .iter()
+                    .map(|input| {
+                        Flow::new(input.material, production_channel.hourly_rate(input.value))
+                    })
+                    .collect(),
+                outputs: production_channel
+                    .outputs
+                    .iter()
+                    .map(|output| {
+                        Flow::new(
+                            output.material,
+                            production_channel.hourly_rate(output.value),
+                        )
+                    })
+                    .collect(),
+            });
+        }
+    }
+    recipes.sort_by(|a, b| {
+        (&a.building, &a.upgrade, a.channel).cmp(&(&b.building, &b.upgrade, b.channel))
+    });
+
+    recipes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{build_structures, setup_test_structure_maps};
+
+    #[test]
+    fn test_recipes() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        assert_eq!(
+            rg.recipes(Material::Coke),
+            vec![
+                Recipe {
+                    building: "upgrade_a".to_string(),
+                    upgrade: None,
+                    channel: 0,
+                    power: 1.0,
+                    inputs: vec![Flow::new(Material::Coal, 1.0)],
+                    outputs: vec![Flow::new(Material::Coke, 1.0)],
+                },
+                Recipe {
+                    building: "upgrade_a".to_string(),
+                    upgrade: Some("upgrade_a_1".to_string()),
+                    channel: 0,
+                    power: 1.0,
+                    inputs: vec![Flow::new(Material::Coal, 1.0)],
+                    outputs: vec![Flow::new(Material::Coke, 2.0)],
+                },
+            ]
+        );
+        assert!(rg.recipes(Material::Water).is_empty());
+    }
+
+    #[test]
+    fn test_uses() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let uses = rg.uses(Material::Components);
+        assert_eq!(uses.len(), 2);
+        assert!(uses
+            .iter()
+            .all(|recipe| recipe.building == "upgrade_b" && recipe.upgrade.is_none()));
+        assert_eq!(
+            uses.iter().map(|recipe| recipe.channel).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert_eq!(rg.uses(Material::Coke)[0].building, "upgrade_c");
+    }
+
+    #[test]
+    fn test_bundled_catalog() {
+        let rg = ResourceGraph::default();
+
+        let structures = rg.structures();
+        assert!(structures
+            .windows(2)
+            .all(|pair| pair[0].building < pair[1].building));
+        assert!(!rg.uses(Material::Coal).is_empty());
+        for recipe in rg.uses(Material::Coal) {
+            assert!(recipe
+                .inputs
+                .iter()
+                .any(|input| input.material == Material::Coal));
+        }
+    }
+
+    #[test]
+    fn test_material_infos() {
+        let infos = material_infos();
+        let bmats = infos
+            .iter()
+            .find(|info| info.material == Material::BasicMaterials)
+            .unwrap();
+
+        assert_eq!(bmats.name, "Basic Materials");
+        assert_eq!(bmats.cli_name, "basic-materials");
+        assert_eq!(bmats.aliases, vec!["bmats"]);
+        assert_eq!(infos.len(), Material::iter().count());
+    }
+
+    #[test]
+    fn test_sum_targets() {
+        let totals = sum_targets(vec![
+            (Material::Pipe, 10),
+            (Material::Coke, 5),
+            (Material::Pipe, 20),
+        ])
+        .unwrap();
+        assert_eq!(
+            totals,
+            vec![(Material::Pipe, 30), (Material::Coke, 5)]
+                .into_iter()
+                .collect()
+        );
+
+        let err = sum_targets(vec![(Material::Pipe, u64::MAX), (Material::Pipe, 1)]).unwrap_err();
+        assert_eq!(err, TargetError::RateTooLarge(Material::Pipe));
+        assert_eq!(err.to_string(), "Total rate for Pipe is too large");
+        assert_eq!(
+            sum_targets(vec![(Material::Pipe, u64::MAX - 1), (Material::Pipe, 1)]).unwrap()
+                [&Material::Pipe],
+            u64::MAX
+        );
+    }
+}
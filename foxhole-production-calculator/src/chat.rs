@@ -0,0 +1,484 @@
+//! Chat commands for bots, such as `!calc 120 shell150, have coke`, and compact Markdown replies
+//! that fit within a message length limit.
+//!
+//! A command is [`CHAT_PREFIX`] followed by clauses separated by commas or semicolons:
+//!
+//! - `<rate> <material>` or `<material> <rate>` adds a target. [Unit/Hour]
+//! - `have <material> [and <material>...]` brings materials in from outside the factory. Any
+//!   later clause without a rate is read as another input.
+//! - `without <structure> [and <structure>...]` never uses a structure.
+//! - `prefer <structure>[:<upgrade>] for <material>` prefers an upgrade for a material.
+//!
+//! Material names are matched by [`Material::parse_loose`], and structure names ignore case and
+//! punctuation.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+
+use foxhole_production_calculator_types::parse::normalize;
+use foxhole_production_calculator_types::{Material, ParseMaterialError};
+
+use crate::preferences::{RecipePreferences, UpgradeRef};
+use crate::{FactoryRequirements, ResourceGraph};
+
+pub const CHAT_PREFIX: &str = "!calc";
+
+/// Discord's message length limit.
+pub const DEFAULT_CHAT_LIMIT: usize = 2000;
+
+/// An upgrade preferred for a material, as typed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatPreference {
+    pub material: Material,
+    pub building: String,
+    pub upgrade: Option<String>,
+}
+
+/// A parsed chat command, ready to calculate.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChatCommand {
+    /// [Unit/Hour]
+    pub targets: BTreeMap<Material, u64>,
+    pub inputs: BTreeSet<Material>,
+    /// Structure names as typed.
+    pub excluded: Vec<String>,
+    pub preferred: Vec<ChatPreference>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChatError {
+    /// The text doesn't start with [`CHAT_PREFIX`].
+    NotACommand,
+    NoTargets,
+    /// A clause that couldn't be read.
+    Malformed(String),
+    /// A material given without a rate before any `have`.
+    MissingRate(Material),
+    Material(ParseMaterialError),
+    UnknownStructure(String),
+    UnknownUpgrade {
+        building: String,
+        upgrade: String,
+    },
+}
+
+impl fmt::Display for ChatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatError::NotACommand => write!(f, "Commands start with {}", CHAT_PREFIX),
+            ChatError::NoTargets => write!(f, "Nothing to make, try {} 120 shell150", CHAT_PREFIX),
+            ChatError::Malformed(clause) => write!(f, "Couldn't understand '{}'", clause),
+            ChatError::MissingRate(material) => {
+                write!(f, "How much {} per hour? Put the rate before it", material)
+            }
+            ChatError::Material(e) => write!(f, "{}", e),
+            ChatError::UnknownStructure(building) => write!(f, "Unknown structure '{}'", building),
+            ChatError::UnknownUpgrade { building, upgrade } => {
+                write!(f, "{} has no upgrade '{}'", building, upgrade)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChatError {}
+
+impl From<ParseMaterialError> for ChatError {
+    fn from(e: ParseMaterialError) -> Self {
+        ChatError::Material(e)
+    }
+}
+
+impl ChatCommand {
+    pub fn parse(text: &str) -> Result<Self, ChatError> {
+        let text = text.trim();
+        let rest = match text.get(..CHAT_PREFIX.len()) {
+            Some(prefix) if prefix.eq_ignore_ascii_case(CHAT_PREFIX) => &text[CHAT_PREFIX.len()..],
+            _ => return Err(ChatError::NotACommand),
+        };
+        if rest.starts_with(|c: char| !c.is_whitespace()) {
+            return Err(ChatError::NotACommand);
+        }
+
+        let mut command = ChatCommand::default();
+        let mut after_have = false;
+        for clause in rest.split([',', ';']).map(str::trim) {
+            if clause.is_empty() {
+                continue;
+            }
+
+            let (keyword, body) = clause
+                .split_once(char::is_whitespace)
+                .unwrap_or((clause, ""));
+            match keyword.to_ascii_lowercase().as_str() {
+                "have" | "with" => {
+                    after_have = true;
+                    for name in list(body) {
+                        command.inputs.insert(Material::parse_loose(name)?);
+                    }
+                }
+                "without" | "no" => command.excluded.extend(list(body).map(str::to_string)),
+                "prefer" => {
+                    let (building, material) = body
+                        .rsplit_once(" for ")
+                        .ok_or_else(|| ChatError::Malformed(clause.to_string()))?;
+                    let (building, upgrade) = match building.split_once(':') {
+                        Some((building, upgrade)) => (building, Some(upgrade.trim().to_string())),
+                        None => (building, None),
+                    };
+                    command.preferred.push(ChatPreference {
+                        material: Material::parse_loose(material)?,
+                        building: building.trim().to_string(),
+                        upgrade,
+                    });
+                }
+                _ => match target(clause)? {
+                    (material, Some(rate)) => {
+                        let total = command.targets.entry(material).or_default();
+                        *total = total
+                            .checked_add(rate)
+                            .ok_or_else(|| ChatError::Malformed(clause.to_string()))?;
+                    }
+                    (material, None) if after_have => {
+                        command.inputs.insert(material);
+                    }
+                    (material, None) => return Err(ChatError::MissingRate(material)),
+                },
+            }
+        }
+
+        if command.targets.is_empty() {
+            return Err(ChatError::NoTargets);
+        }
+
+        Ok(command)
+    }
+}
+
+/// Items of a `have` or `without` clause.
+fn list(body: &str) -> impl Iterator<Item = &str> {
+    body.split(" and ")
+        .flat_map(|item| item.split('&'))
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+/// A material with the rate before or after it, if there is one.
+fn target(clause: &str) -> Result<(Material, Option<u64>), ChatError> {
+    let words: Vec<&str> = clause.split_whitespace().collect();
+    let rate = |word: &str| word.trim_end_matches("/h").parse::<u64>().ok();
+
+    let (name, rate) = match words.as_slice() {
+        [first, rest @ ..] if !rest.is_empty() && rate(first).is_some() => {
+            (rest.join(" "), rate(first))
+        }
+        [rest @ .., last] if !rest.is_empty() && rate(last).is_some() => {
+            (rest.join(" "), rate(last))
+        }
+        [] => return Err(ChatError::Malformed(clause.to_string())),
+        _ => (clause.to_string(), None),
+    };
+
+    Ok((Material::parse_loose(&name)?, rate))
+}
+
+impl ResourceGraph {
+    /// Resolve the structures named in a command against the structure data.
+    pub fn chat_preferences(&self, command: &ChatCommand) -> Result<RecipePreferences, ChatError> {
+        let mut preferences = self.preferences.clone();
+        for building in &command.excluded {
+            preferences = preferences.exclude_structure(&self.chat_structure(building)?);
+        }
+        for preference in &command.preferred {
+            let building = self.chat_structure(&preference.building)?;
+            let upgrade = match &preference.upgrade {
+                Some(upgrade) => Some(self.chat_upgrade(&building, upgrade)?),
+                None => None,
+            };
+            preferences = preferences.prefer(
+                preference.material,
+                UpgradeRef::new(&building, upgrade.as_deref()),
+            );
+        }
+
+        Ok(preferences)
+    }
+
+    pub fn chat_requirements(
+        &self,
+        command: &ChatCommand,
+    ) -> Result<FactoryRequirements, ChatError> {
+        let graph = self
+            .clone()
+            .with_preferences(self.chat_preferences(command)?);
+        let inputs: HashSet<Material> = command.inputs.iter().copied().collect();
+        let trees = graph.calculate_factory_requirements(
+            command
+                .targets
+                .iter()
+                .map(|(material, rate)| (*material, *rate))
+                .collect(),
+            inputs.clone(),
+        );
+
+        Ok(graph.factory_requirements_from_trees(&trees, inputs))
+    }
+
+    /// Reply to a chat message, or `None` if it isn't a command. Errors are replied with too.
+    pub fn chat_reply(&self, text: &str, limit: usize) -> Option<String> {
+        let result = ChatCommand::parse(text).and_then(|command| {
+            self.chat_requirements(&command)
+                .map(|reqs| render_chat(&command, &reqs, limit))
+        });
+
+        match result {
+            Ok(reply) => Some(reply),
+            Err(ChatError::NotACommand) => None,
+            Err(e) => Some(fit(format!("**Error:** {}", e), limit)),
+        }
+    }
+
+    fn chat_structure(&self, name: &str) -> Result<String, ChatError> {
+        let key = normalize(name);
+        self.catalog
+            .structure_map
+            .keys()
+            .find(|building| normalize(building) == key)
+            .cloned()
+            .ok_or_else(|| ChatError::UnknownStructure(name.to_string()))
+    }
+
+    fn chat_upgrade(&self, building: &str, name: &str) -> Result<String, ChatError> {
+        let key = normalize(name);
+        self.catalog.structure_map[building]
+            .upgrades
+            .keys()
+            .find(|upgrade| normalize(upgrade) == key)
+            .cloned()
+            .ok_or_else(|| ChatError::UnknownUpgrade {
+                building: building.to_string(),
+                upgrade: name.to_string(),
+            })
+    }
+}
+
+/// A labelled, comma separated line of the reply.
+struct Line {
+    label: &'static str,
+    items: Vec<String>,
+    /// Items shown, the rest are counted as "+N more".
+    shown: usize,
+}
+
+impl Line {
+    fn new(label: &'static str, items: Vec<String>) -> Self {
+        let shown = items.len();
+
+        Self {
+            label,
+            items,
+            shown,
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut items = self.items[..self.shown].to_vec();
+        if self.shown < self.items.len() {
+            items.push(format!("+{} more", self.items.len() - self.shown));
+        }
+
+        format!("**{}:** {}", self.label, items.join(", "))
+    }
+}
+
+/// Render requirements as Markdown of at most `limit` characters. Long lists are shortened
+/// first, then the least important lines are dropped, and only then is the text cut off.
+pub fn render_chat(command: &ChatCommand, reqs: &FactoryRequirements, limit: usize) -> String {
+    let targets: Vec<String> = command
+        .targets
+        .iter()
+        .map(|(material, rate)| format!("{}/h {}", rate, material))
+        .collect();
+    let mut header = format!("**{}**", targets.join(" + "));
+    if !command.inputs.is_empty() {
+        let inputs: Vec<String> = command.inputs.iter().map(Material::to_string).collect();
+        header.push_str(&format!(" (have {})", inputs.join(", ")));
+    }
+
+    let buildings = reqs
+        .buildings
+        .iter()
+        .map(|building| match &building.upgrade {
+            Some(upgrade) => format!(
+                "{}x {} ({})",
+                number(building.count),
+                building.building,
+                upgrade
+            ),
+            None => format!("{}x {}", number(building.count), building.building),
+        })
+        .collect();
+    let inputs: BTreeMap<_, _> = reqs.inputs.iter().collect();
+    let build_cost: BTreeMap<_, _> = reqs.build_cost.iter().collect();
+
+    // In order of importance, most important first.
+    let mut lines = vec![
+        Line::new("Buildings", buildings),
+        Line::new(
+            "Inputs/h",
+            inputs
+                .into_iter()
+                .map(|(material, rate)| format!("{} {}", number(*rate), material))
+                .collect(),
+        ),
+        Line::new("Power", vec![format!("{} MW", number(reqs.power))]),
+        Line::new(
+            "Cost",
+            build_cost
+                .into_iter()
+                .map(|(material, amount)| format!("{} {}", amount, material))
+                .collect(),
+        ),
+    ];
+    lines.retain(|line| !line.items.is_empty());
+
+    let render = |lines: &[Line]| {
+        let mut reply = header.clone();
+        for line in lines {
+            reply.push('\n');
+            reply.push_str(&line.render());
+        }
+
+        reply
+    };
+
+    let mut reply = render(&lines);
+    while reply.chars().count() > limit {
+        // Later lines lose items first when lists are the same length.
+        let longest = (0..lines.len())
+            .filter(|idx| lines[*idx].shown > 1)
+            .max_by_key(|idx| lines[*idx].shown);
+        match longest {
+            Some(idx) => lines[idx].shown -= 1,
+            None if lines.len() > 1 => {
+                lines.pop();
+            }
+            None => break,
+        }
+        reply = render(&lines);
+    }
+
+    fit(reply, limit)
+}
+
+/// Cut text down to `limit` characters, marking the cut with an ellipsis.
+fn fit(text: String, limit: usize) -> String {
+    if text.chars().count() <= limit {
+        return text;
+    }
+
+    let mut cut: String = text.chars().take(limit.saturating_sub(1)).collect();
+    cut.push('…');
+
+    cut
+}
+
+/// At most two decimal places, without trailing zeros.
+fn number(value: f32) -> String {
+    let text = format!("{:.2}", value);
+
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{build_structures, setup_test_structure_maps};
+
+    #[test]
+    fn test_parse() {
+        let command = ChatCommand::parse("!calc 120 shell150, have coke").unwrap();
+        assert_eq!(
+            command.targets,
+            vec![(Material::Shell150MM, 120)].into_iter().collect()
+        );
+        assert_eq!(command.inputs, vec![Material::Coke].into_iter().collect());
+
+        let command = ChatCommand::parse(
+            "!CALC bmats 50; 20/h 150s; have coal and comps, salvage; without Coal Refinery; \
+             prefer materials factory:smelter for cmats",
+        )
+        .unwrap();
+        assert_eq!(command.targets.len(), 2);
+        assert_eq!(command.inputs.len(), 3);
+        assert_eq!(command.excluded, vec!["Coal Refinery".to_string()]);
+        assert_eq!(
+            command.preferred,
+            vec![ChatPreference {
+                material: Material::ConstructionMaterials,
+                building: "materials factory".to_string(),
+                upgrade: Some("smelter".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            ChatCommand::parse("!calculate 5 pipe"),
+            Err(ChatError::NotACommand)
+        );
+        assert_eq!(ChatCommand::parse("!calc"), Err(ChatError::NoTargets));
+        assert_eq!(
+            ChatCommand::parse("!calc coke"),
+            Err(ChatError::MissingRate(Material::Coke))
+        );
+        assert!(matches!(
+            ChatCommand::parse("!calc 5 shell"),
+            Err(ChatError::Material(_))
+        ));
+        assert_eq!(
+            ChatCommand::parse(&format!("!calc {} coke, 1 coke", u64::MAX)),
+            Err(ChatError::Malformed("1 coke".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_chat_reply() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let reply = rg
+            .chat_reply("!calc 2 concrete, prefer UPGRADE A for coke", 2000)
+            .unwrap();
+        assert_eq!(
+            reply,
+            "**2/h Concrete Materials**\n\
+             **Buildings:** 2x upgrade_a, 2x upgrade_c\n\
+             **Inputs/h:** 2 Coal\n\
+             **Power:** 4 MW\n\
+             **Cost:** 4 Basic Materials"
+        );
+
+        assert_eq!(rg.chat_reply("hello", 2000), None);
+        assert_eq!(
+            rg.chat_reply("!calc 2 concrete, without upgrade_z", 2000),
+            Some("**Error:** Unknown structure 'upgrade_z'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_reply_limit() {
+        let rg = ResourceGraph::default();
+        let command = ChatCommand::parse("!calc 100 150s, 100 120s, 100 75s").unwrap();
+        let reqs = rg.chat_requirements(&command).unwrap();
+
+        let full = render_chat(&command, &reqs, DEFAULT_CHAT_LIMIT);
+        assert!(!full.contains("more"));
+
+        let short = render_chat(&command, &reqs, 150);
+        assert!(short.chars().count() <= 150);
+        assert!(short.contains("more") || short.ends_with('…'));
+        assert!(short.starts_with("**100/h Shell 75mm + 100/h Shell 120mm + 100/h Shell 150mm**"));
+    }
+}
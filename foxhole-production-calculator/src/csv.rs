@@ -0,0 +1,292 @@
+//! CSV export of a plan, one section at a time, for use in spreadsheets.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::report::active_chain;
+use crate::{FactoryRequirements, ResourceGraph, StructureTree};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsvSection {
+    Buildings,
+    Inputs,
+    BuildCost,
+    StockpileUsage,
+    /// Every active node of every tree.
+    Chain,
+}
+
+// Headers are written separately from the rows, so sections without any rows still have one.
+const BUILDINGS_HEADER: &[&str] = &["building", "upgrade", "count"];
+const INPUTS_HEADER: &[&str] = &["material", "rate"];
+const BUILD_COST_HEADER: &[&str] = &["material", "amount"];
+const STOCKPILE_USAGE_HEADER: &[&str] = &["material", "consumer", "rate", "amount"];
+const CHAIN_HEADER: &[&str] = &[
+    "tree", "node", "parent", "building", "upgrade", "channel", "count", "material", "rate",
+    "power",
+];
+
+#[derive(Serialize)]
+struct InputRow {
+    material: String,
+    /// [Unit/Hour]
+    rate: f32,
+}
+
+#[derive(Serialize)]
+struct BuildCostRow {
+    material: String,
+    amount: u64,
+}
+
+#[derive(Serialize)]
+struct StockpileUsageRow {
+    material: String,
+    consumer: Option<String>,
+    /// [Unit/Hour]
+    rate: f32,
+    amount: f32,
+}
+
+#[derive(Serialize)]
+struct ChainRow {
+    tree: usize,
+    node: String,
+    /// Node this one supplies, empty for a tree's root.
+    parent: Option<String>,
+    building: String,
+    upgrade: Option<String>,
+    channel: usize,
+    count: f32,
+    material: String,
+    /// [Unit/Hour]
+    rate: f32,
+    power: f32,
+}
+
+impl FactoryRequirements {
+    /// `building,upgrade,count`
+    pub fn buildings_csv(&self) -> String {
+        write_rows(BUILDINGS_HEADER, &self.buildings)
+    }
+
+    /// `material,rate`, in material order.
+    pub fn inputs_csv(&self) -> String {
+        let ordered: BTreeMap<_, _> = self.inputs.iter().collect();
+        let rows: Vec<InputRow> = ordered
+            .into_iter()
+            .map(|(material, rate)| InputRow {
+                material: material.to_string(),
+                rate: *rate,
+            })
+            .collect();
+
+        write_rows(INPUTS_HEADER, &rows)
+    }
+
+    /// `material,amount`, in material order.
+    pub fn build_cost_csv(&self) -> String {
+        let ordered: BTreeMap<_, _> = self.build_cost.iter().collect();
+        let rows: Vec<BuildCostRow> = ordered
+            .into_iter()
+            .map(|(material, amount)| BuildCostRow {
+                material: material.to_string(),
+                amount: *amount,
+            })
+            .collect();
+
+        write_rows(BUILD_COST_HEADER, &rows)
+    }
+
+    /// `material,consumer,rate,amount`, one row per branch drawing from the stockpile.
+    pub fn stockpile_usage_csv(&self) -> String {
+        let rows: Vec<StockpileUsageRow> = self
+            .stockpile_usage
+            .iter()
+            .map(|usage| StockpileUsageRow {
+                material: usage.material.to_string(),
+                consumer: usage.consumer.clone(),
+                rate: usage.rate,
+                amount: usage.amount,
+            })
+            .collect();
+
+        write_rows(STOCKPILE_USAGE_HEADER, &rows)
+    }
+}
+
+impl ResourceGraph {
+    /// `tree,node,parent,building,upgrade,channel,count,material,rate,power` for every active
+    /// node, parents before their children. `parent` refers to the `node` column of the same tree.
+    pub fn chain_csv(&self, trees: &[StructureTree]) -> String {
+        let mut rows = Vec::new();
+        for (tree_idx, tree) in trees.iter().enumerate() {
+            for node_id in active_chain(tree) {
+                let details = self.node_details(tree, node_id).expect("Node should exist");
+                rows.push(ChainRow {
+                    tree: tree_idx,
+                    node: node_id.to_string(),
+                    parent: details.parent.map(|parent| parent.to_string()),
+                    building: details.building,
+                    upgrade: details.upgrade,
+                    channel: details.channel,
+                    count: details.count,
+                    material: details.output.material.to_string(),
+                    rate: details.output.rate,
+                    power: details.power,
+                });
+            }
+        }
+
+        write_rows(CHAIN_HEADER, &rows)
+    }
+
+    pub fn to_csv(
+        &self,
+        trees: &[StructureTree],
+        reqs: &FactoryRequirements,
+        section: CsvSection,
+    ) -> String {
+        match section {
+            CsvSection::Buildings => reqs.buildings_csv(),
+            CsvSection::Inputs => reqs.inputs_csv(),
+            CsvSection::BuildCost => reqs.build_cost_csv(),
+            CsvSection::StockpileUsage => reqs.stockpile_usage_csv(),
+            CsvSection::Chain => self.chain_csv(trees),
+        }
+    }
+}
+
+fn write_rows<T: Serialize>(header: &[&str], rows: &[T]) -> String {
+    let mut writer = ::csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    writer
+        .write_record(header)
+        .expect("Writing to memory can't fail");
+    for row in rows {
+        writer.serialize(row).expect("Rows should serialize");
+    }
+    let bytes = writer.into_inner().expect("Writing to memory can't fail");
+
+    String::from_utf8(bytes).expect("CSV should be valid UTF-8")
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use foxhole_production_calculator_types::Material;
+
+    use super::*;
+    use crate::stockpile::Stockpile;
+    use crate::test::{build_structures, setup_test_structure_maps};
+
+    #[test]
+    fn test_buildings_csv() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::ConcreteMaterials, 2)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+
+        assert_eq!(
+            reqs.buildings_csv(),
+            "building,upgrade,count\nupgrade_a,upgrade_a_1,1.0\nupgrade_c,,2.0\n"
+        );
+        assert_eq!(reqs.inputs_csv(), "material,rate\nCoal,1.0\n");
+        assert_eq!(
+            rg.to_csv(&trees, &reqs, CsvSection::BuildCost),
+            "material,amount\nBasic Materials,4\n"
+        );
+    }
+
+    #[test]
+    fn test_chain_csv_parents() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::ConcreteMaterials, 2)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let root = trees[0].roots.as_ref().unwrap()[0];
+
+        let csv = rg.chain_csv(&trees);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "tree,node,parent,building,upgrade,channel,count,material,rate,power"
+        );
+        assert_eq!(
+            lines[1],
+            format!("0,{},,upgrade_c,,0,2.0,Concrete Materials,2.0,2.0", root)
+        );
+        assert!(lines[2].starts_with("0,"));
+        assert!(lines[2].contains(&format!(",{},upgrade_a,upgrade_a_1,", root)));
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn test_csv_quoting() {
+        let reqs = FactoryRequirements {
+            buildings: vec![crate::FactoryRequirementsBuilding {
+                building: "Factory, Large".to_string(),
+                upgrade: None,
+                count: 1.5,
+            }],
+            channels: Vec::new(),
+            power: 0.0,
+            build_cost: Default::default(),
+            inputs: Default::default(),
+            stockpile_usage: Vec::new(),
+            balance: Vec::new(),
+        };
+
+        assert_eq!(
+            reqs.buildings_csv(),
+            "building,upgrade,count\n\"Factory, Large\",,1.5\n"
+        );
+    }
+
+    #[test]
+    fn test_empty_sections_keep_headers() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        // Without a stockpile there are no usage rows, and an empty chain has no trees.
+        let outputs = vec![(Material::Coke, 2)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+
+        assert!(reqs.stockpile_usage.is_empty());
+        assert_eq!(
+            rg.to_csv(&trees, &reqs, CsvSection::StockpileUsage),
+            "material,consumer,rate,amount\n"
+        );
+        assert_eq!(rg.chain_csv(&[]), format!("{}\n", CHAIN_HEADER.join(",")));
+    }
+
+    #[test]
+    fn test_stockpile_usage_csv() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::ConcreteMaterials, 12)]
+            .into_iter()
+            .collect();
+        let stockpile = Stockpile::new(vec![(Material::Coke, 10)].into_iter().collect(), 1.0);
+        let trees =
+            rg.calculate_factory_requirements_with_stockpile(outputs, HashSet::new(), &stockpile);
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+
+        assert_eq!(
+            reqs.stockpile_usage_csv(),
+            "material,consumer,rate,amount\nCoke,upgrade_c,10.0,10.0\n"
+        );
+    }
+}
@@ -0,0 +1,169 @@
+//! Structured per node data for rendering trees.
+
+use std::collections::HashMap;
+
+use foxhole_production_calculator_types::Material;
+use indextree::NodeId;
+use serde::Serialize;
+
+use crate::{calculate_build_costs, ResourceGraph, StructureTree};
+
+/// A material moving at an hourly rate.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Flow {
+    pub material: Material,
+    /// [Unit/Hour]
+    pub rate: f32,
+}
+
+impl Flow {
+    pub fn new(material: Material, rate: f32) -> Self {
+        Self { material, rate }
+    }
+}
+
+/// Everything known about a single tree node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeDetails {
+    pub id: NodeId,
+    pub building: String,
+    pub upgrade: Option<String>,
+    /// Index of the production channel the node runs.
+    pub channel: usize,
+    pub count: f32,
+    pub active: bool,
+    pub output: Flow,
+    /// Inputs at the full rate, before anything is taken from a stockpile.
+    pub inputs: Vec<Flow>,
+    pub power: f32,
+    /// Cost of building this node's structures, on top of any other node's.
+    pub build_cost: HashMap<Material, u64>,
+    pub parent: Option<NodeId>,
+    pub children: Vec<NodeId>,
+    /// Every alternative to the node, including the node itself.
+    pub alternatives: Vec<NodeId>,
+    /// Distance from the tree's roots, which are at depth 0.
+    pub depth: usize,
+}
+
+impl ResourceGraph {
+    pub fn node_details(&self, tree: &StructureTree, node_id: NodeId) -> Option<NodeDetails> {
+        let arena_node = tree.get_arena_node(node_id)?;
+        let node = arena_node.get();
+        let (building, upgrade) = node.structure.building_key();
+        let production_channel = self.production_channel(&node.structure);
+
+        let output = Flow::new(
+            node.structure.output.material,
+            production_channel.hourly_rate(node.structure.output.value) * node.count,
+        );
+        let inputs = production_channel
+            .inputs
+            .iter()
+            .map(|input| {
+                Flow::new(
+                    input.material,
+                    production_channel.hourly_rate(input.value) * node.count,
+                )
+            })
+            .collect();
+
+        let mut build_cost = HashMap::new();
+        calculate_build_costs(
+            &mut build_cost,
+            &self.structure(&node.structure).default_upgrade,
+            node.count,
+        );
+        if node.structure.parent.is_some() {
+            calculate_build_costs(&mut build_cost, self.upgrade(&node.structure), node.count);
+        }
+
+        Some(NodeDetails {
+            id: node_id,
+            building,
+            upgrade,
+            channel: node.structure.prod_channel_idx,
+            count: node.count,
+            active: node.active,
+            output,
+            inputs,
+            power: production_channel.power * node.count.ceil(),
+            build_cost,
+            parent: arena_node.parent(),
+            children: node_id.children(&tree.arena).collect(),
+            alternatives: tree.options(node).to_vec(),
+            depth: node_id.ancestors(&tree.arena).count() - 1,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::test::{build_structures, setup_test_structure_maps};
+
+    #[test]
+    fn test_root_details() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::ConcreteMaterials, 2)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let root = trees[0].roots.as_ref().unwrap()[0];
+        let details = rg.node_details(&trees[0], root).unwrap();
+
+        assert_eq!(details.building, "upgrade_c");
+        assert_eq!(details.upgrade, None);
+        assert_eq!(details.count, 2.0);
+        assert_eq!(details.output, Flow::new(Material::ConcreteMaterials, 2.0));
+        assert_eq!(details.inputs, vec![Flow::new(Material::Coke, 2.0)]);
+        assert_eq!(details.power, 2.0);
+        assert_eq!(
+            details.build_cost,
+            vec![(Material::BasicMaterials, 2)].into_iter().collect()
+        );
+        assert_eq!(details.parent, None);
+        assert_eq!(details.children.len(), 2);
+        assert_eq!(details.alternatives, vec![root]);
+        assert_eq!(details.depth, 0);
+    }
+
+    #[test]
+    fn test_fractional_and_inactive_details() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::ConcreteMaterials, 1)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let root = trees[0].roots.as_ref().unwrap()[0];
+        let children: Vec<NodeId> = root.children(&trees[0].arena).collect();
+
+        // Half a building still draws full power and costs a whole building and upgrade.
+        let active = rg.node_details(&trees[0], children[0]).unwrap();
+        assert!(active.active);
+        assert_eq!(active.upgrade, Some("upgrade_a_1".to_string()));
+        assert_eq!(active.count, 0.5);
+        assert_eq!(active.inputs, vec![Flow::new(Material::Coal, 0.5)]);
+        assert_eq!(active.power, 1.0);
+        assert_eq!(
+            active.build_cost,
+            vec![(Material::BasicMaterials, 2)].into_iter().collect()
+        );
+
+        // An inactive alternative is sized for the same output, but has no children until it's
+        // activated.
+        let inactive = rg.node_details(&trees[0], children[1]).unwrap();
+        assert!(!inactive.active);
+        assert_eq!(inactive.upgrade, None);
+        assert_eq!(inactive.count, 1.0);
+        assert_eq!(inactive.output, active.output);
+        assert_eq!(inactive.parent, Some(root));
+        assert_eq!(inactive.depth, 1);
+        assert!(inactive.children.is_empty());
+        assert_eq!(inactive.alternatives, children);
+    }
+}
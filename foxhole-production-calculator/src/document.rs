@@ -0,0 +1,226 @@
+//! Stable, versioned form of [`FactoryRequirements`] for other programs to read.
+//!
+//! Maps are ordered by material and lists by building, so the same plan always serializes to
+//! the same JSON. The layout is described by [`REQUIREMENTS_SCHEMA`], and any change to it bumps
+//! [`REQUIREMENTS_SCHEMA_VERSION`].
+
+use std::collections::BTreeMap;
+
+use foxhole_production_calculator_types::Material;
+use serde::{Deserialize, Serialize};
+
+use crate::FactoryRequirements;
+
+pub const REQUIREMENTS_SCHEMA_VERSION: u32 = 1;
+
+/// JSON Schema for [`RequirementsDocument`].
+pub const REQUIREMENTS_SCHEMA: &str = include_str!("../schema/requirements-v1.schema.json");
+
+/// Units used for every value in a [`RequirementsDocument`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Units {
+    pub rate: String,
+    pub power: String,
+    pub amount: String,
+}
+
+impl Default for Units {
+    fn default() -> Self {
+        Self {
+            rate: "units/hour".to_string(),
+            power: "MW".to_string(),
+            amount: "units".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DocumentBuilding {
+    pub building: String,
+    pub upgrade: Option<String>,
+    pub count: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DocumentChannel {
+    pub building: String,
+    pub upgrade: Option<String>,
+    pub channel: usize,
+    pub count: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DocumentStockpileUsage {
+    pub material: Material,
+    pub consumer: Option<String>,
+    pub rate: f32,
+    pub amount: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DocumentBalance {
+    pub material: Material,
+    pub produced: f32,
+    pub consumed: f32,
+    pub imported: f32,
+    pub exported: f32,
+    pub surplus: f32,
+}
+
+/// Factory requirements as published by the CLI.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequirementsDocument {
+    pub schema_version: u32,
+    pub units: Units,
+    /// Materials leaving the factory. [rate]
+    pub targets: BTreeMap<Material, f32>,
+    /// [amount]
+    pub buildings: Vec<DocumentBuilding>,
+    pub channels: Vec<DocumentChannel>,
+    /// [power]
+    pub power: f32,
+    /// [amount]
+    pub build_cost: BTreeMap<Material, u64>,
+    /// Brought in from outside the factory. [rate]
+    pub inputs: BTreeMap<Material, f32>,
+    pub stockpile_usage: Vec<DocumentStockpileUsage>,
+    pub balance: Vec<DocumentBalance>,
+}
+
+impl From<&FactoryRequirements> for RequirementsDocument {
+    fn from(reqs: &FactoryRequirements) -> Self {
+        Self {
+            schema_version: REQUIREMENTS_SCHEMA_VERSION,
+            units: Units::default(),
+            targets: reqs
+                .balance
+                .iter()
+                .filter(|row| row.exported > 0.0)
+                .map(|row| (row.material, row.exported))
+                .collect(),
+            buildings: reqs
+                .buildings
+                .iter()
+                .map(|building| DocumentBuilding {
+                    building: building.building.clone(),
+                    upgrade: building.upgrade.clone(),
+                    count: building.count,
+                })
+                .collect(),
+            channels: reqs
+                .channels
+                .iter()
+                .map(|channel| DocumentChannel {
+                    building: channel.building.clone(),
+                    upgrade: channel.upgrade.clone(),
+                    channel: channel.channel,
+                    count: channel.count,
+                })
+                .collect(),
+            power: reqs.power,
+            build_cost: reqs.build_cost.iter().map(|(k, v)| (*k, *v)).collect(),
+            inputs: reqs.inputs.iter().map(|(k, v)| (*k, *v)).collect(),
+            stockpile_usage: reqs
+                .stockpile_usage
+                .iter()
+                .map(|usage| DocumentStockpileUsage {
+                    material: usage.material,
+                    consumer: usage.consumer.clone(),
+                    rate: usage.rate,
+                    amount: usage.amount,
+                })
+                .collect(),
+            balance: reqs
+                .balance
+                .iter()
+                .map(|row| DocumentBalance {
+                    material: row.material,
+                    produced: row.produced,
+                    consumed: row.consumed,
+                    imported: row.imported,
+                    exported: row.exported,
+                    surplus: row.surplus,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use serde_json::Value;
+    use strum::IntoEnumIterator;
+
+    use super::*;
+    use crate::test::{build_structures, setup_test_structure_maps};
+    use crate::ResourceGraph;
+
+    fn document() -> RequirementsDocument {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::ConcreteMaterials, 2), (Material::Coke, 4)]
+            .into_iter()
+            .collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+
+        RequirementsDocument::from(&reqs)
+    }
+
+    #[test]
+    fn test_document_is_deterministic() {
+        let json = serde_json::to_string(&document()).unwrap();
+        for _ in 0..5 {
+            assert_eq!(serde_json::to_string(&document()).unwrap(), json);
+        }
+
+        assert!(json.starts_with("{\"schema_version\":1,"));
+        assert!(json.contains("\"targets\":{\"Coke\":4.0,\"ConcreteMaterials\":2.0}"));
+    }
+
+    #[test]
+    fn test_document_round_trip() {
+        let document = document();
+        let json = serde_json::to_string(&document).unwrap();
+
+        assert_eq!(
+            serde_json::from_str::<RequirementsDocument>(&json).unwrap(),
+            document
+        );
+    }
+
+    #[test]
+    fn test_schema_matches_document() {
+        let schema: Value = serde_json::from_str(REQUIREMENTS_SCHEMA).unwrap();
+        let document = serde_json::to_value(document()).unwrap();
+
+        let mut required: Vec<&str> = schema["required"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| field.as_str().unwrap())
+            .collect();
+        let mut fields: Vec<&str> = document
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        required.sort_unstable();
+        fields.sort_unstable();
+        assert_eq!(required, fields);
+        assert_eq!(
+            schema["properties"]["schema_version"]["const"],
+            REQUIREMENTS_SCHEMA_VERSION
+        );
+
+        let materials: Vec<Value> = Material::iter()
+            .map(|material| serde_json::to_value(material).unwrap())
+            .collect();
+        assert_eq!(schema["$defs"]["material"]["enum"], Value::from(materials));
+    }
+}
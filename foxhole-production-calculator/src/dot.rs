@@ -0,0 +1,374 @@
+//! Graphviz DOT export of production chains.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use foxhole_production_calculator_types::Material;
+
+use crate::{FactoryRequirements, ResourceGraph, StructureTree};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DotOptions {
+    /// Draw a single node for every building, upgrade and channel, summed across trees.
+    pub collapse_duplicates: bool,
+    /// Also draw the alternatives to active nodes, dashed.
+    pub show_inactive: bool,
+}
+
+struct DotBuilding {
+    label: String,
+    count: f32,
+    active: bool,
+}
+
+impl ResourceGraph {
+    /// Render trees as a DOT digraph. Buildings and materials are nodes, and edges carry hourly
+    /// rates from input materials into buildings and from buildings to their output.
+    pub fn to_dot(&self, trees: &[StructureTree], options: &DotOptions) -> String {
+        let mut buildings: BTreeMap<String, DotBuilding> = BTreeMap::new();
+        let mut materials: BTreeSet<Material> = BTreeSet::new();
+        let mut edges: BTreeMap<(String, String, bool), f32> = BTreeMap::new();
+
+        for (tree_idx, tree) in trees.iter().enumerate() {
+            let mut stack = tree.roots.clone().unwrap_or_default();
+            while let Some(node_id) = stack.pop() {
+                let node = tree.get_node(node_id).expect("Node should exist");
+                if !node.active && !options.show_inactive {
+                    continue;
+                }
+
+                let details = self.node_details(tree, node_id).expect("Node should exist");
+                let label = building_label(&details.building, details.upgrade.as_deref());
+                let id = if options.collapse_duplicates {
+                    format!(
+                        "building:{}:{}:{}:{}",
+                        details.building,
+                        details.upgrade.as_deref().unwrap_or_default(),
+                        details.channel,
+                        if node.active { "active" } else { "inactive" }
+                    )
+                } else {
+                    format!("building:{}:{}", tree_idx, node_id)
+                };
+
+                let building = buildings.entry(id.clone()).or_insert(DotBuilding {
+                    label,
+                    count: 0.0,
+                    active: node.active,
+                });
+                building.count += details.count;
+
+                let dashed = !node.active;
+                materials.insert(details.output.material);
+                *edges
+                    .entry((id.clone(), material_id(details.output.material), dashed))
+                    .or_default() += details.output.rate;
+                for input in &details.inputs {
+                    materials.insert(input.material);
+                    *edges
+                        .entry((material_id(input.material), id.clone(), dashed))
+                        .or_default() += input.rate;
+                }
+
+                if node.active {
+                    stack.extend(node_id.children(&tree.arena));
+                }
+            }
+        }
+
+        write_dot(materials, buildings, edges)
+    }
+
+    /// Render factory requirements as a DOT digraph, with one node for each building, upgrade
+    /// and channel the requirements run. Edges carry hourly rates like [`Self::to_dot`].
+    pub fn requirements_to_dot(&self, reqs: &FactoryRequirements) -> String {
+        let mut buildings: BTreeMap<String, DotBuilding> = BTreeMap::new();
+        let mut materials: BTreeSet<Material> = BTreeSet::new();
+        let mut edges: BTreeMap<(String, String, bool), f32> = BTreeMap::new();
+
+        for channel in &reqs.channels {
+            let structure = self
+                .catalog
+                .structure_map
+                .get(&channel.building)
+                .expect("Structure should exist");
+            let upgrade = match &channel.upgrade {
+                Some(upgrade) => structure
+                    .upgrades
+                    .get(upgrade)
+                    .expect("Upgrade should exist"),
+                None => &structure.default_upgrade,
+            };
+            let production_channel = &upgrade.production_channels[channel.channel];
+
+            let id = format!(
+                "building:{}:{}:{}",
+                channel.building,
+                channel.upgrade.as_deref().unwrap_or_default(),
+                channel.channel
+            );
+            buildings.insert(
+                id.clone(),
+                DotBuilding {
+                    label: building_label(&channel.building, channel.upgrade.as_deref()),
+                    count: channel.count,
+                    active: true,
+                },
+            );
+
+            for output in &production_channel.outputs {
+                materials.insert(output.material);
+                *edges
+                    .entry((id.clone(), material_id(output.material), false))
+                    .or_default() += production_channel.hourly_rate(output.value) * channel.count;
+            }
+            for input in &production_channel.inputs {
+                materials.insert(input.material);
+                *edges
+                    .entry((material_id(input.material), id.clone(), false))
+                    .or_default() += production_channel.hourly_rate(input.value) * channel.count;
+            }
+        }
+
+        write_dot(materials, buildings, edges)
+    }
+}
+
+fn write_dot(
+    materials: BTreeSet<Material>,
+    buildings: BTreeMap<String, DotBuilding>,
+    edges: BTreeMap<(String, String, bool), f32>,
+) -> String {
+    let mut dot = String::new();
+    writeln!(dot, "digraph production {{").unwrap();
+    writeln!(dot, "    rankdir=LR;").unwrap();
+    for material in materials {
+        writeln!(
+            dot,
+            "    \"{}\" [label=\"{}\", shape=ellipse];",
+            escape(&material_id(material)),
+            escape(&material.to_string())
+        )
+        .unwrap();
+    }
+    for (id, building) in buildings {
+        let style = if building.active {
+            ""
+        } else {
+            ", style=dashed"
+        };
+        writeln!(
+            dot,
+            "    \"{}\" [label=\"{}\\n{:.2}x\", shape=box{}];",
+            escape(&id),
+            building.label,
+            building.count,
+            style
+        )
+        .unwrap();
+    }
+    for ((from, to, dashed), rate) in edges {
+        let style = if dashed { ", style=dashed" } else { "" };
+        writeln!(
+            dot,
+            "    \"{}\" -> \"{}\" [label=\"{:.2}/h\"{}];",
+            escape(&from),
+            escape(&to),
+            rate,
+            style
+        )
+        .unwrap();
+    }
+    writeln!(dot, "}}").unwrap();
+
+    dot
+}
+
+/// Building name, and upgrade name on the next line, escaped for a DOT label.
+fn building_label(building: &str, upgrade: Option<&str>) -> String {
+    match upgrade {
+        Some(upgrade) => format!("{}\\n{}", escape(building), escape(upgrade)),
+        None => escape(building),
+    }
+}
+
+/// Escape a string for use inside a quoted DOT ID.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn material_id(material: Material) -> String {
+    format!("material:{}", material)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use foxhole_production_calculator_types::{
+        BuildCost, Footprint, Input, Output, ProductionChannel, Structure, Upgrade,
+    };
+
+    use super::*;
+    use crate::test::{build_structures, setup_test_structure_maps};
+
+    #[test]
+    fn test_dot_active_chain() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::ConcreteMaterials, 1)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let dot = rg.to_dot(&trees, &DotOptions::default());
+
+        assert!(dot.starts_with("digraph production {\n"));
+        assert!(dot.contains("\"material:Coke\" [label=\"Coke\", shape=ellipse];"));
+        assert!(dot.contains("[label=\"upgrade_a\\nupgrade_a_1\\n0.50x\", shape=box];"));
+        assert!(dot.contains("\"material:Coal\" -> \"building:0:2\" [label=\"0.50/h\"];"));
+        assert!(!dot.contains("dashed"));
+    }
+
+    #[test]
+    fn test_dot_collapse_duplicates() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::ConcreteMaterials, 1), (Material::Coke, 10)]
+            .into_iter()
+            .collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let options = DotOptions {
+            collapse_duplicates: true,
+            ..Default::default()
+        };
+        let dot = rg.to_dot(&trees, &options);
+
+        assert_eq!(dot.matches("\\nupgrade_a_1\\n").count(), 1);
+        assert!(dot.contains(
+            "\"building:upgrade_a:upgrade_a_1:0:active\" -> \"material:Coke\" [label=\"11.00/h\"];"
+        ));
+    }
+
+    #[test]
+    fn test_dot_inactive_kept_apart_when_collapsed() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        // Each tree runs the building the other one has as its inactive alternative.
+        let outputs = vec![(Material::Coke, 10), (Material::ConcreteMaterials, 1)]
+            .into_iter()
+            .collect();
+        let mut trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let slower = trees[0].roots.clone().unwrap()[1];
+        trees[0].activate_node(slower);
+
+        let options = DotOptions {
+            collapse_duplicates: true,
+            show_inactive: true,
+        };
+        let dot = rg.to_dot(&trees, &options);
+
+        assert!(dot.contains(
+            "\"building:upgrade_a::0:active\" [label=\"upgrade_a\\n10.00x\", shape=box];"
+        ));
+        assert!(dot.contains(
+            "\"building:upgrade_a::0:inactive\" [label=\"upgrade_a\\n1.00x\", shape=box, style=dashed];"
+        ));
+        assert!(dot.contains("\"building:upgrade_a:upgrade_a_1:0:active\" [label=\"upgrade_a\\nupgrade_a_1\\n0.50x\", shape=box];"));
+        assert!(dot.contains("\"building:upgrade_a:upgrade_a_1:0:inactive\" [label=\"upgrade_a\\nupgrade_a_1\\n5.00x\", shape=box, style=dashed];"));
+        assert!(dot.contains(
+            "\"material:Coal\" -> \"building:upgrade_a::0:active\" [label=\"10.00/h\"];"
+        ));
+        assert!(dot.contains(
+            "\"material:Coal\" -> \"building:upgrade_a::0:inactive\" [label=\"1.00/h\", style=dashed];"
+        ));
+
+        // Without show_inactive, neither inactive alternative is drawn.
+        let dot = rg.to_dot(
+            &trees,
+            &DotOptions {
+                collapse_duplicates: true,
+                ..Default::default()
+            },
+        );
+        assert!(!dot.contains(":inactive"));
+        assert!(!dot.contains("dashed"));
+    }
+
+    #[test]
+    fn test_requirements_to_dot() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::ConcreteMaterials, 1), (Material::Coke, 10)]
+            .into_iter()
+            .collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+        let dot = rg.requirements_to_dot(&reqs);
+
+        assert!(dot.starts_with("digraph production {\n"));
+        assert!(dot.contains(
+            "\"building:upgrade_a:upgrade_a_1:0\" [label=\"upgrade_a\\nupgrade_a_1\\n5.50x\", shape=box];"
+        ));
+        assert!(dot.contains(
+            "\"building:upgrade_a:upgrade_a_1:0\" -> \"material:Coke\" [label=\"11.00/h\"];"
+        ));
+        assert!(dot.contains(
+            "\"material:Coal\" -> \"building:upgrade_a:upgrade_a_1:0\" [label=\"5.50/h\"];"
+        ));
+        assert!(dot.contains("\"material:Coke\" -> \"building:upgrade_c::0\" [label=\"1.00/h\"];"));
+        assert_eq!(dot.matches("shape=box").count(), reqs.channels.len());
+    }
+
+    #[test]
+    fn test_dot_escapes_names() {
+        let name = "Bob's \"Big\" \\ Press";
+        let structure = Structure::new(
+            Footprint::new(2, 2),
+            Upgrade::new(
+                name.to_string(),
+                vec![BuildCost::new(Material::BasicMaterials, 1)],
+                vec![ProductionChannel {
+                    power: 1.0,
+                    rate: 3600,
+                    inputs: vec![Input::new(Material::Coal, 1)],
+                    outputs: vec![Output::new(Material::Coke, 1)],
+                }],
+                None,
+            ),
+            Default::default(),
+        );
+        let structures = vec![structure];
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::Coke, 1)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+        let escaped = "Bob's \\\"Big\\\" \\\\ Press";
+
+        let dot = rg.to_dot(
+            &trees,
+            &DotOptions {
+                collapse_duplicates: true,
+                ..Default::default()
+            },
+        );
+        assert!(dot.contains(&format!(
+            "\"building:{}::0:active\" [label=\"{}\\n1.00x\", shape=box];",
+            escaped, escaped
+        )));
+
+        let dot = rg.requirements_to_dot(&reqs);
+        assert!(dot.contains(&format!(
+            "\"building:{}::0\" [label=\"{}\\n1.00x\", shape=box];",
+            escaped, escaped
+        )));
+        assert!(!dot.contains(name));
+    }
+}
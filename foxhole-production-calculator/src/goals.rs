@@ -0,0 +1,317 @@
+//! Goals phrased as quantities over time rather than hourly rates.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use foxhole_production_calculator_types::Material;
+use serde::{Deserialize, Serialize};
+
+use crate::{FactoryRequirementsBuilding, ResourceGraph, StructureTree};
+
+/// Produce a fixed quantity of a material before a deadline.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct QuantityGoal {
+    pub material: Material,
+    pub quantity: u64,
+    /// Time available, in hours.
+    pub hours: f32,
+}
+
+impl QuantityGoal {
+    /// Fails unless `hours` is a positive, finite number.
+    pub fn new(material: Material, quantity: u64, hours: f32) -> Result<Self, GoalError> {
+        if !(hours.is_finite() && hours > 0.0) {
+            return Err(GoalError::InvalidHours(hours));
+        }
+
+        Ok(Self {
+            material,
+            quantity,
+            hours,
+        })
+    }
+
+    /// Rate needed to meet the goal, rounded up to a whole unit. [Unit/Hour]
+    pub fn hourly_rate(&self) -> u64 {
+        (self.quantity as f32 / self.hours).ceil() as u64
+    }
+}
+
+/// How long a fixed set of buildings takes to produce a quantity of a material.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProductionEstimate {
+    pub material: Material,
+    pub quantity: u64,
+    /// Highest rate the buildings can sustain. [Unit/Hour]
+    pub rate: f32,
+    pub hours: f32,
+    /// The available buildings that limit the rate.
+    pub bottleneck: FactoryRequirementsBuilding,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GoalError {
+    /// Producing the material needs a building that isn't in the given set.
+    MissingBuilding {
+        building: String,
+        upgrade: Option<String>,
+    },
+    /// The material isn't made by any building.
+    NotProducible(Material),
+    /// The time available isn't a positive number of hours.
+    InvalidHours(f32),
+    /// A given building's count isn't a positive number.
+    InvalidBuildingCount {
+        building: String,
+        upgrade: Option<String>,
+        count: f32,
+    },
+}
+
+impl fmt::Display for GoalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoalError::MissingBuilding {
+                building,
+                upgrade: Some(upgrade),
+            } => write!(f, "Production requires a {} ({})", building, upgrade),
+            GoalError::MissingBuilding {
+                building,
+                upgrade: None,
+            } => write!(f, "Production requires a {}", building),
+            GoalError::NotProducible(material) => {
+                write!(f, "{} isn't produced by any structure", material)
+            }
+            GoalError::InvalidHours(hours) => {
+                write!(f, "Hours must be a positive number, got {}", hours)
+            }
+            GoalError::InvalidBuildingCount {
+                building,
+                upgrade: Some(upgrade),
+                count,
+            } => write!(
+                f,
+                "The count of {} ({}) must be a positive number, got {}",
+                building, upgrade, count
+            ),
+            GoalError::InvalidBuildingCount {
+                building,
+                upgrade: None,
+                count,
+            } => write!(
+                f,
+                "The count of {} must be a positive number, got {}",
+                building, count
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GoalError {}
+
+impl ResourceGraph {
+    /// Calculate factory requirements for quantity goals. Goals for the same material are
+    /// combined.
+    pub fn calculate_goal_requirements(
+        &self,
+        goals: &[QuantityGoal],
+        user_inputs: HashSet<Material>,
+    ) -> Vec<StructureTree> {
+        let mut outputs: HashMap<Material, u64> = HashMap::new();
+        for goal in goals {
+            let rate = outputs.entry(goal.material).or_default();
+            *rate = rate.saturating_add(goal.hourly_rate());
+        }
+
+        self.calculate_factory_requirements(outputs, user_inputs)
+    }
+
+    /// Estimate how long the given buildings take to produce a quantity of a material.
+    ///
+    /// Where a step has alternatives, one using the given buildings is picked. The rate is then
+    /// limited by whichever building is most overloaded.
+    pub fn estimate_production_time(
+        &self,
+        material: Material,
+        quantity: u64,
+        buildings: &[FactoryRequirementsBuilding],
+        user_inputs: HashSet<Material>,
+    ) -> Result<ProductionEstimate, GoalError> {
+        let mut available: HashMap<(String, Option<String>), f32> = HashMap::new();
+        for building in buildings {
+            if !(building.count.is_finite() && building.count > 0.0) {
+                return Err(GoalError::InvalidBuildingCount {
+                    building: building.building.clone(),
+                    upgrade: building.upgrade.clone(),
+                    count: building.count,
+                });
+            }
+            *available
+                .entry((building.building.clone(), building.upgrade.clone()))
+                .or_default() += building.count;
+        }
+
+        // Counts scale linearly with the rate, so plan for a single unit an hour.
+        let outputs = vec![(material, 1)].into_iter().collect();
+        let mut trees = self.calculate_factory_requirements(outputs, user_inputs.clone());
+        if trees.iter().all(|tree| tree.roots.is_none()) {
+            return Err(GoalError::NotProducible(material));
+        }
+
+        for tree in &mut trees {
+            prefer_available_buildings(tree, &available);
+        }
+
+        let reqs = self.factory_requirements_from_trees(&trees, user_inputs);
+        let mut estimate: Option<(f32, FactoryRequirementsBuilding)> = None;
+        for required in reqs.buildings {
+            let key = (required.building.clone(), required.upgrade.clone());
+            let count = available.get(&key).copied().unwrap_or_default();
+            if count <= 0.0 {
+                return Err(GoalError::MissingBuilding {
+                    building: required.building,
+                    upgrade: required.upgrade,
+                });
+            }
+
+            let rate = count / required.count;
+            if estimate.as_ref().is_none_or(|(lowest, _)| rate < *lowest) {
+                estimate = Some((rate, FactoryRequirementsBuilding { count, ..required }));
+            }
+        }
+
+        let (rate, bottleneck) = estimate.expect("Production should need at least one building");
+
+        Ok(ProductionEstimate {
+            material,
+            quantity,
+            rate,
+            hours: quantity as f32 / rate,
+            bottleneck,
+        })
+    }
+}
+
+/// Walk the active nodes top down, switching to an alternative made from available buildings
+/// wherever the current choice isn't available.
+fn prefer_available_buildings(
+    tree: &mut StructureTree,
+    available: &HashMap<(String, Option<String>), f32>,
+) {
+    let mut stack = tree.roots.clone().unwrap_or_default();
+    while let Some(node_id) = stack.pop() {
+        let node = tree.get_node(node_id).expect("Node should exist");
+        if !node.is_active() {
+            continue;
+        }
+
+        if !available.contains_key(&node.structure.building_key()) {
+            let replacement = tree.options(node).iter().copied().find(|option_id| {
+                let option = tree.get_node(*option_id).expect("Node should exist");
+                available.contains_key(&option.structure.building_key())
+            });
+
+            if let Some(replacement) = replacement {
+                tree.activate_node(replacement);
+                stack.push(replacement);
+                continue;
+            }
+        }
+
+        stack.extend(node_id.children(&tree.arena));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{build_structures, setup_test_structure_maps};
+
+    #[test]
+    fn test_goal_requirements_rate() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let goals = [
+            QuantityGoal::new(Material::Coke, 50, 6.0).unwrap(),
+            QuantityGoal::new(Material::Coke, 10, 5.0).unwrap(),
+        ];
+        let trees = rg.calculate_goal_requirements(&goals, HashSet::new());
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+
+        // 9 + 2 units an hour from the Coke producing upgrade at 2 units an hour each.
+        assert_eq!(reqs.buildings[0].count, 5.5);
+
+        for hours in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(matches!(
+                QuantityGoal::new(Material::Coke, 50, hours),
+                Err(GoalError::InvalidHours(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_estimate_uses_available_alternative() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let buildings = [FactoryRequirementsBuilding {
+            building: "upgrade_a".to_string(),
+            upgrade: None,
+            count: 2.0,
+        }];
+        let estimate = rg
+            .estimate_production_time(Material::Coke, 10, &buildings, HashSet::new())
+            .unwrap();
+
+        assert_eq!(estimate.rate, 2.0);
+        assert_eq!(estimate.hours, 5.0);
+        assert_eq!(estimate.bottleneck, buildings[0]);
+    }
+
+    #[test]
+    fn test_estimate_missing_building() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let buildings = [FactoryRequirementsBuilding {
+            building: "upgrade_c".to_string(),
+            upgrade: None,
+            count: 1.0,
+        }];
+        let err = rg
+            .estimate_production_time(Material::ConcreteMaterials, 10, &buildings, HashSet::new())
+            .unwrap_err();
+
+        assert_eq!(
+            err,
+            GoalError::MissingBuilding {
+                building: "upgrade_a".to_string(),
+                upgrade: Some("upgrade_a_1".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_estimate_invalid_building_count() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        for count in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            let buildings = [FactoryRequirementsBuilding {
+                building: "upgrade_a".to_string(),
+                upgrade: None,
+                count,
+            }];
+            let err = rg
+                .estimate_production_time(Material::Coke, 10, &buildings, HashSet::new())
+                .unwrap_err();
+
+            assert!(matches!(err, GoalError::InvalidBuildingCount { .. }));
+        }
+    }
+}
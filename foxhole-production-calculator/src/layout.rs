@@ -0,0 +1,748 @@
+//! Placement of planned structures on a facility plot.
+//!
+//! Buildings are packed onto a grid in rows with corridors left between them.
+//! Pipes and power lines are then routed through those corridors.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::{self, Write};
+
+use foxhole_production_calculator_types::{Footprint, Material};
+use indextree::NodeId;
+use serde::Serialize;
+
+use crate::{ResourceGraph, StructureTree};
+
+/// Free cells kept between buildings and around the plot edge for routing.
+const CORRIDOR: u32 = 2;
+
+/// Size of a grid cell when rendering to SVG, in pixels.
+const SVG_CELL_SIZE: u64 = 10;
+
+/// Most grid cells a plot can have. Routing keeps a few bytes per cell, so anything much bigger
+/// than a real facility would take more memory than it's worth.
+pub const MAX_LAYOUT_CELLS: u64 = 4_000_000;
+
+/// Label characters. Past the last one, labels grow to two characters and so on.
+const PLACEMENT_LABELS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// Dimensions of a facility plot in meters, one meter per grid cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LayoutArea {
+    pub width: u32,
+    pub length: u32,
+}
+
+impl LayoutArea {
+    pub fn new(width: u32, length: u32) -> Self {
+        Self { width, length }
+    }
+
+    fn contains(&self, x: i64, y: i64) -> bool {
+        x >= 0 && y >= 0 && x < self.width as i64 && y < self.length as i64
+    }
+
+    /// Number of grid cells, which doesn't fit in a `u32` for the largest plots.
+    pub fn cells(&self) -> u64 {
+        self.width as u64 * self.length as u64
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+}
+
+/// A single building placed on the plot. `x` and `y` are the top-left cell.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Placement {
+    pub building: String,
+    pub upgrade: Option<String>,
+    pub x: u32,
+    pub y: u32,
+    pub footprint: Footprint,
+    /// Power drawn by the building in MW.
+    pub power: f32,
+}
+
+impl Placement {
+    #[cfg(test)]
+    fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x
+            && y >= self.y
+            && x < self.x + self.footprint.width
+            && y < self.y + self.footprint.length
+    }
+
+    fn center(&self) -> (u32, u32) {
+        (
+            self.x + self.footprint.width / 2,
+            self.y + self.footprint.length / 2,
+        )
+    }
+
+    fn name(&self) -> String {
+        match &self.upgrade {
+            Some(upgrade) => format!("{} ({})", self.building, upgrade),
+            None => self.building.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum ConnectionKind {
+    Pipe(Material),
+    Power,
+}
+
+impl fmt::Display for ConnectionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionKind::Pipe(material) => write!(f, "{} pipe", material),
+            ConnectionKind::Power => write!(f, "power line"),
+        }
+    }
+}
+
+/// Where a connection starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Endpoint {
+    /// Index into [`FacilityLayout::placements`].
+    Placement(usize),
+    /// The west edge of the plot, where liquids from outside the facility are piped in.
+    Intake,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Connection {
+    pub kind: ConnectionKind,
+    pub from: Endpoint,
+    /// Index into [`FacilityLayout::placements`].
+    pub to: usize,
+    /// Cells the connection runs through, in order.
+    pub path: Vec<(u32, u32)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FacilityLayout {
+    pub area: LayoutArea,
+    pub placements: Vec<Placement>,
+    pub connections: Vec<Connection>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayoutError {
+    /// The plot has more than [`MAX_LAYOUT_CELLS`] cells.
+    AreaTooLarge(LayoutArea),
+    /// The named building didn't fit in the remaining space.
+    InsufficientSpace { building: String },
+    /// No free path exists to connect the given placement.
+    NoRoute { kind: ConnectionKind, to: usize },
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutError::AreaTooLarge(area) => write!(
+                f,
+                "A {}x{} facility area is over the limit of {} cells",
+                area.width, area.length, MAX_LAYOUT_CELLS
+            ),
+            LayoutError::InsufficientSpace { building } => {
+                write!(f, "Not enough space in the facility area for {}", building)
+            }
+            LayoutError::NoRoute { kind, to } => {
+                write!(f, "Could not route a {} to placement {}", kind, to)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LayoutError {}
+
+type BuildingKey = (String, Option<String>);
+
+#[derive(Default)]
+struct BuildingGroup {
+    count: f32,
+    power: f32,
+    footprint: Footprint,
+}
+
+impl ResourceGraph {
+    /// Place the buildings from the active nodes of the given trees on a plot, then route pipes
+    /// for liquids and power lines between them.
+    ///
+    /// Fractional building counts are rounded up, as a partially used building still needs the
+    /// space. Plots over [`MAX_LAYOUT_CELLS`] are rejected.
+    pub fn plan_layout(
+        &self,
+        trees: &[StructureTree],
+        area: LayoutArea,
+    ) -> Result<FacilityLayout, LayoutError> {
+        if area.cells() > MAX_LAYOUT_CELLS {
+            return Err(LayoutError::AreaTooLarge(area));
+        }
+
+        let mut groups: BTreeMap<BuildingKey, BuildingGroup> = BTreeMap::new();
+        let mut pipes = BTreeSet::new();
+        let mut intakes = BTreeSet::new();
+
+        for tree in trees {
+            for node_id in active_node_ids(tree) {
+                let arena_node = tree.arena.get(node_id).expect("Node should exist");
+                let node = arena_node.get();
+                let key = node.structure.building_key();
+                let production_channel = self.production_channel(&node.structure);
+
+                let group = groups.entry(key.clone()).or_default();
+                group.count += node.count;
+                group.power = group.power.max(production_channel.power);
+                group.footprint = self.structure(&node.structure).footprint;
+
+                let output_material = node.structure.output.material;
+                if output_material.is_liquid() {
+                    if let Some(parent_id) = arena_node.parent() {
+                        let parent = tree.get_node(parent_id).expect("Node should exist");
+                        pipes.insert((
+                            key.clone(),
+                            parent.structure.building_key(),
+                            output_material,
+                        ));
+                    }
+                }
+
+                for input in &production_channel.inputs {
+                    let produced_here = node_id.children(&tree.arena).any(|child| {
+                        let child = tree.get_node(child).expect("Node should exist");
+                        child.is_active() && child.structure.output.material == input.material
+                    });
+
+                    if input.material.is_liquid() && !produced_here {
+                        intakes.insert((key.clone(), input.material));
+                    }
+                }
+            }
+        }
+
+        let mut placements = Vec::new();
+        let mut instances: BTreeMap<BuildingKey, Vec<usize>> = BTreeMap::new();
+        // Worked out in `u64`, so rows running past the end of the largest plots can't wrap.
+        let (area_width, area_length) = (area.width as u64, area.length as u64);
+        let corridor = CORRIDOR as u64;
+        let (mut cursor_x, mut cursor_y, mut row_length) = (corridor, corridor, 0);
+        for ((building, upgrade), group) in groups {
+            let (width, length) = (group.footprint.width as u64, group.footprint.length as u64);
+            for _ in 0..group.count.ceil() as usize {
+                if cursor_x + width + corridor > area_width {
+                    cursor_x = corridor;
+                    cursor_y += row_length + corridor;
+                    row_length = 0;
+                }
+
+                if cursor_x + width + corridor > area_width
+                    || cursor_y + length + corridor > area_length
+                {
+                    let building = match &upgrade {
+                        Some(upgrade) => format!("{} ({})", building, upgrade),
+                        None => building,
+                    };
+
+                    return Err(LayoutError::InsufficientSpace { building });
+                }
+
+                instances
+                    .entry((building.clone(), upgrade.clone()))
+                    .or_default()
+                    .push(placements.len());
+                placements.push(Placement {
+                    building: building.clone(),
+                    upgrade: upgrade.clone(),
+                    x: cursor_x as u32,
+                    y: cursor_y as u32,
+                    footprint: group.footprint,
+                    power: group.power,
+                });
+
+                cursor_x += width + corridor;
+                row_length = row_length.max(length);
+            }
+        }
+
+        let grid = RoutingGrid::new(area, &placements);
+        let mut connections = Vec::new();
+
+        for (producer, consumer, material) in pipes {
+            let kind = ConnectionKind::Pipe(material);
+            for &to in &instances[&consumer] {
+                let from = nearest(&placements, &instances[&producer], to);
+                let path = grid
+                    .route(&grid.border(&placements[from]), &placements[to])
+                    .ok_or(LayoutError::NoRoute { kind, to })?;
+
+                connections.push(Connection {
+                    kind,
+                    from: Endpoint::Placement(from),
+                    to,
+                    path,
+                });
+            }
+        }
+
+        for (consumer, material) in intakes {
+            let kind = ConnectionKind::Pipe(material);
+            for &to in &instances[&consumer] {
+                let path = grid
+                    .route(&grid.west_edge(), &placements[to])
+                    .ok_or(LayoutError::NoRoute { kind, to })?;
+
+                connections.push(Connection {
+                    kind,
+                    from: Endpoint::Intake,
+                    to,
+                    path,
+                });
+            }
+        }
+
+        // Chain powered buildings together, connecting each to its closest powered neighbour.
+        let powered: Vec<usize> = (0..placements.len())
+            .filter(|idx| placements[*idx].power > 0.0)
+            .collect();
+        for (connected, &to) in powered.iter().enumerate().skip(1) {
+            let kind = ConnectionKind::Power;
+            let from = nearest(&placements, &powered[..connected], to);
+            let path = grid
+                .route(&grid.border(&placements[from]), &placements[to])
+                .ok_or(LayoutError::NoRoute { kind, to })?;
+
+            connections.push(Connection {
+                kind,
+                from: Endpoint::Placement(from),
+                to,
+                path,
+            });
+        }
+
+        Ok(FacilityLayout {
+            area,
+            placements,
+            connections,
+        })
+    }
+}
+
+fn active_node_ids(tree: &StructureTree) -> Vec<NodeId> {
+    let mut node_ids = Vec::new();
+    let mut stack: Vec<NodeId> = tree.roots.iter().flatten().copied().collect();
+    while let Some(node_id) = stack.pop() {
+        if !tree
+            .get_node(node_id)
+            .expect("Node should exist")
+            .is_active()
+        {
+            continue;
+        }
+
+        node_ids.push(node_id);
+        stack.extend(node_id.children(&tree.arena));
+    }
+
+    node_ids
+}
+
+fn nearest(placements: &[Placement], candidates: &[usize], target: usize) -> usize {
+    let (target_x, target_y) = placements[target].center();
+
+    *candidates
+        .iter()
+        .min_by_key(|idx| {
+            let (x, y) = placements[**idx].center();
+            x.abs_diff(target_x) + y.abs_diff(target_y)
+        })
+        .expect("There should be at least one candidate")
+}
+
+struct RoutingGrid {
+    area: LayoutArea,
+    blocked: Vec<bool>,
+}
+
+impl RoutingGrid {
+    fn new(area: LayoutArea, placements: &[Placement]) -> Self {
+        let mut blocked = vec![false; area.cells() as usize];
+        for placement in placements {
+            for y in placement.y..placement.y + placement.footprint.length {
+                for x in placement.x..placement.x + placement.footprint.width {
+                    blocked[area.index(x, y)] = true;
+                }
+            }
+        }
+
+        Self { area, blocked }
+    }
+
+    fn is_free(&self, x: i64, y: i64) -> bool {
+        self.area.contains(x, y) && !self.blocked[self.area.index(x as u32, y as u32)]
+    }
+
+    /// Free cells directly next to a placement's walls.
+    fn border(&self, placement: &Placement) -> Vec<(u32, u32)> {
+        let (x0, y0) = (placement.x as i64 - 1, placement.y as i64 - 1);
+        let x1 = (placement.x + placement.footprint.width) as i64;
+        let y1 = (placement.y + placement.footprint.length) as i64;
+
+        let horizontal = (x0 + 1..x1).flat_map(|x| [(x, y0), (x, y1)]);
+        let vertical = (y0 + 1..y1).flat_map(|y| [(x0, y), (x1, y)]);
+
+        horizontal
+            .chain(vertical)
+            .filter(|(x, y)| self.is_free(*x, *y))
+            .map(|(x, y)| (x as u32, y as u32))
+            .collect()
+    }
+
+    fn west_edge(&self) -> Vec<(u32, u32)> {
+        (0..self.area.length)
+            .filter(|y| self.is_free(0, *y as i64))
+            .map(|y| (0, y))
+            .collect()
+    }
+
+    /// Breadth first search from any of the start cells to the border of the target placement.
+    fn route(&self, starts: &[(u32, u32)], target: &Placement) -> Option<Vec<(u32, u32)>> {
+        let goals = self.border(target);
+        let mut previous: Vec<Option<(u32, u32)>> = vec![None; self.blocked.len()];
+        let mut visited = vec![false; self.blocked.len()];
+        let mut queue = VecDeque::new();
+
+        for &(x, y) in starts {
+            visited[self.area.index(x, y)] = true;
+            queue.push_back((x, y));
+        }
+
+        while let Some((x, y)) = queue.pop_front() {
+            if goals.contains(&(x, y)) {
+                let mut path = vec![(x, y)];
+                let mut cell = (x, y);
+                while let Some(prev) = previous[self.area.index(cell.0, cell.1)] {
+                    path.push(prev);
+                    cell = prev;
+                }
+                path.reverse();
+
+                return Some(path);
+            }
+
+            for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+                let (next_x, next_y) = (x as i64 + dx, y as i64 + dy);
+                if !self.is_free(next_x, next_y) {
+                    continue;
+                }
+
+                let next = (next_x as u32, next_y as u32);
+                let next_idx = self.area.index(next.0, next.1);
+                if !visited[next_idx] {
+                    visited[next_idx] = true;
+                    previous[next_idx] = Some((x, y));
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl FacilityLayout {
+    /// Render the layout as a character grid followed by a legend.
+    ///
+    /// Buildings are filled with the first character of their label, with the whole label written
+    /// along their top row as far as it fits. Pipes are drawn as `~`, power lines as `+` and cells
+    /// carrying both as `#`.
+    pub fn to_ascii(&self) -> String {
+        let width = self.area.width as usize;
+        let mut grid = vec![b'.'; self.area.cells() as usize];
+
+        for connection in &self.connections {
+            let symbol = match connection.kind {
+                ConnectionKind::Pipe(_) => b'~',
+                ConnectionKind::Power => b'+',
+            };
+
+            for &(x, y) in &connection.path {
+                let cell = &mut grid[self.area.index(x, y)];
+                *cell = match *cell {
+                    b'.' => symbol,
+                    current if current == symbol => symbol,
+                    _ => b'#',
+                };
+            }
+        }
+
+        for (idx, placement) in self.placements.iter().enumerate() {
+            let label = placement_label(idx);
+            let fill = label.as_bytes()[0];
+            for y in placement.y..placement.y + placement.footprint.length {
+                for x in placement.x..placement.x + placement.footprint.width {
+                    grid[self.area.index(x, y)] = fill;
+                }
+            }
+            for (x, symbol) in
+                (placement.x..placement.x + placement.footprint.width).zip(label.bytes())
+            {
+                grid[self.area.index(x, placement.y)] = symbol;
+            }
+        }
+
+        let mut out = String::new();
+        for row in grid.chunks(width.max(1)) {
+            out.push_str(&String::from_utf8_lossy(row));
+            out.push('\n');
+        }
+
+        out.push('\n');
+        for (idx, placement) in self.placements.iter().enumerate() {
+            writeln!(out, "{}: {}", placement_label(idx), placement.name()).unwrap();
+        }
+
+        out
+    }
+
+    /// Render the layout as a standalone SVG document.
+    pub fn to_svg(&self) -> String {
+        // Pixel sizes are worked out in `u64`, as the widest plots overflow a `u32`.
+        let width = self.area.width as u64 * SVG_CELL_SIZE;
+        let length = self.area.length as u64 * SVG_CELL_SIZE;
+        let mut out = String::new();
+
+        writeln!(
+            out,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{length}" viewBox="0 0 {width} {length}">"#
+        )
+        .unwrap();
+        writeln!(
+            out,
+            r##"<rect width="{width}" height="{length}" fill="#f4f1e8"/>"##
+        )
+        .unwrap();
+
+        for (idx, placement) in self.placements.iter().enumerate() {
+            let x = placement.x as u64 * SVG_CELL_SIZE;
+            let y = placement.y as u64 * SVG_CELL_SIZE;
+            let w = placement.footprint.width as u64 * SVG_CELL_SIZE;
+            let l = placement.footprint.length as u64 * SVG_CELL_SIZE;
+
+            writeln!(
+                out,
+                r##"<rect x="{x}" y="{y}" width="{w}" height="{l}" fill="#c9b98f" stroke="#3b3a36"><title>{}</title></rect>"##,
+                xml_escape(&placement.name())
+            )
+            .unwrap();
+            writeln!(
+                out,
+                r#"<text x="{}" y="{}" font-size="{}" text-anchor="middle" dominant-baseline="middle">{}</text>"#,
+                x + w / 2,
+                y + l / 2,
+                SVG_CELL_SIZE * 2,
+                placement_label(idx)
+            )
+            .unwrap();
+        }
+
+        for connection in &self.connections {
+            let (color, title) = match connection.kind {
+                ConnectionKind::Pipe(material) => ("#2f6fad", material.to_string()),
+                ConnectionKind::Power => ("#d9a21b", "Power".to_string()),
+            };
+            let points = connection
+                .path
+                .iter()
+                .map(|(x, y)| {
+                    format!(
+                        "{},{}",
+                        *x as u64 * SVG_CELL_SIZE + SVG_CELL_SIZE / 2,
+                        *y as u64 * SVG_CELL_SIZE + SVG_CELL_SIZE / 2
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            writeln!(
+                out,
+                r#"<polyline points="{points}" fill="none" stroke="{color}" stroke-width="3"><title>{title}</title></polyline>"#
+            )
+            .unwrap();
+        }
+
+        out.push_str("</svg>\n");
+
+        out
+    }
+}
+
+/// `A` to `9`, then `AA`, `AB` and so on, so every placement gets its own label.
+fn placement_label(idx: usize) -> String {
+    let base = PLACEMENT_LABELS.len();
+    let mut label = Vec::new();
+    let mut remaining = idx + 1;
+    while remaining > 0 {
+        remaining -= 1;
+        label.push(PLACEMENT_LABELS[remaining % base]);
+        remaining /= base;
+    }
+    label.reverse();
+
+    String::from_utf8(label).expect("Labels should be ASCII")
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+
+    use super::*;
+
+    fn plan(
+        outputs: HashMap<Material, u64>,
+        area: LayoutArea,
+    ) -> Result<FacilityLayout, LayoutError> {
+        let rg = ResourceGraph::default();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+
+        rg.plan_layout(&trees, area)
+    }
+
+    #[test]
+    fn test_layout_places_buildings_without_overlap() {
+        let outputs = vec![(Material::Petrol, 300)].into_iter().collect();
+        let layout = plan(outputs, LayoutArea::new(150, 150)).unwrap();
+
+        assert!(!layout.placements.is_empty());
+        for (idx, a) in layout.placements.iter().enumerate() {
+            assert!(a.x + a.footprint.width <= layout.area.width);
+            assert!(a.y + a.footprint.length <= layout.area.length);
+
+            for b in &layout.placements[idx + 1..] {
+                let overlap = a.x < b.x + b.footprint.width
+                    && b.x < a.x + a.footprint.width
+                    && a.y < b.y + b.footprint.length
+                    && b.y < a.y + a.footprint.length;
+                assert!(!overlap, "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_layout_routes_pipes_and_power() {
+        let outputs = vec![(Material::Petrol, 300)].into_iter().collect();
+        let layout = plan(outputs, LayoutArea::new(150, 150)).unwrap();
+
+        let kinds: HashSet<ConnectionKind> = layout
+            .connections
+            .iter()
+            .map(|connection| connection.kind)
+            .collect();
+        assert!(kinds.contains(&ConnectionKind::Pipe(Material::Oil)));
+        assert!(kinds.contains(&ConnectionKind::Pipe(Material::Water)));
+        assert!(kinds.contains(&ConnectionKind::Power));
+
+        for connection in &layout.connections {
+            assert!(!connection.path.is_empty());
+            for (x, y) in &connection.path {
+                assert!(!layout.placements.iter().any(|p| p.contains(*x, *y)));
+            }
+        }
+
+        let ascii = layout.to_ascii();
+        assert_eq!(
+            ascii.lines().count(),
+            layout.area.length as usize + 1 + layout.placements.len()
+        );
+        assert!(layout.to_svg().starts_with("<svg"));
+    }
+
+    #[test]
+    fn test_layout_insufficient_space() {
+        let outputs = vec![(Material::Petrol, 300)].into_iter().collect();
+        let err = plan(outputs, LayoutArea::new(10, 10)).unwrap_err();
+
+        assert!(matches!(err, LayoutError::InsufficientSpace { .. }));
+    }
+
+    #[test]
+    fn test_layout_area_too_large() {
+        let outputs: HashMap<Material, u64> = vec![(Material::Petrol, 300)].into_iter().collect();
+        let area = LayoutArea::new(u32::MAX, u32::MAX);
+
+        assert_eq!(
+            plan(outputs.clone(), area),
+            Err(LayoutError::AreaTooLarge(area))
+        );
+        assert!(plan(outputs, LayoutArea::new(2000, 2000)).is_ok());
+    }
+
+    #[test]
+    fn test_svg_size_past_u32() {
+        let layout = FacilityLayout {
+            area: LayoutArea::new(u32::MAX, 1),
+            placements: vec![Placement {
+                building: "Materials Factory".to_string(),
+                upgrade: None,
+                x: u32::MAX - 2,
+                y: 0,
+                footprint: Footprint::new(2, 1),
+                power: 0.0,
+            }],
+            connections: vec![Connection {
+                kind: ConnectionKind::Power,
+                from: Endpoint::Placement(0),
+                to: 0,
+                path: vec![(u32::MAX - 1, 0)],
+            }],
+        };
+
+        let svg = layout.to_svg();
+        assert!(svg.contains(r#"width="42949672950" height="10""#));
+        assert!(svg.contains(r#"<rect x="42949672930" y="0" width="20" height="10""#));
+        assert!(svg.contains(r#"points="42949672945,5""#));
+    }
+
+    #[test]
+    fn test_labels_past_single_characters() {
+        assert_eq!(placement_label(0), "A");
+        assert_eq!(placement_label(61), "9");
+        assert_eq!(placement_label(62), "AA");
+        assert_eq!(placement_label(62 + 62 * 62), "AAA");
+        let labels: HashSet<String> = (0..5000).map(placement_label).collect();
+        assert_eq!(labels.len(), 5000);
+
+        let placements: Vec<Placement> = (0..63)
+            .map(|idx| Placement {
+                building: "Materials Factory".to_string(),
+                upgrade: None,
+                x: idx * 3,
+                y: 0,
+                footprint: Footprint::new(2, 2),
+                power: 0.0,
+            })
+            .collect();
+        let layout = FacilityLayout {
+            area: LayoutArea::new(63 * 3, 2),
+            placements,
+            connections: Vec::new(),
+        };
+        let ascii = layout.to_ascii();
+        let rows: Vec<&str> = ascii.lines().collect();
+        assert!(rows[0].starts_with("AA.BB."));
+        assert!(rows[0].ends_with("99.AA."));
+        assert!(rows[1].ends_with("99.AA."));
+        assert!(ascii.ends_with("AA: Materials Factory\n"));
+
+        assert_eq!(LayoutArea::new(u32::MAX, 4).cells(), u32::MAX as u64 * 4);
+    }
+}
@@ -1,15 +1,38 @@
-use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::rc::Rc;
-
+use std::sync::Arc;
+
+pub mod arithmetic;
+pub mod balance;
+pub mod catalog;
+pub mod chat;
+pub mod csv;
+pub mod details;
+pub mod document;
+pub mod dot;
+pub mod goals;
+pub mod layout;
+pub mod plan;
+pub mod preferences;
+pub mod report;
+pub mod sankey;
+pub mod saved;
+pub mod simulation;
+pub mod stockpile;
+mod subtree;
+
+use balance::MaterialBalance;
 use foxhole_production_calculator_types::Material::{self, *};
 use foxhole_production_calculator_types::{
-    BuildCost, Input, Output, ProductionChannel, Structure, Upgrade,
+    BuildCost, Footprint, Input, Output, ProductionChannel, Structure, Upgrade,
 };
 use indextree::{Arena, Node, NodeId};
 use itertools::sorted;
+use preferences::RecipePreferences;
 use serde::Serialize;
+use stockpile::{SharedSupply, Stockpile, StockpileDraw, StockpileSupply, StockpileUsage};
+use subtree::SubtreeCache;
 
 include!(concat!(env!("OUT_DIR"), "/structures.rs"));
 
@@ -28,6 +51,14 @@ impl StructureKey {
             None => self.upgrade.clone(),
         }
     }
+
+    /// Base structure name and, for non-default upgrades, the upgrade name.
+    fn building_key(&self) -> (String, Option<String>) {
+        match &self.parent {
+            Some(parent) => (parent.clone(), Some(self.upgrade.clone())),
+            None => (self.upgrade.clone(), None),
+        }
+    }
 }
 
 impl PartialEq for StructureKey {
@@ -48,32 +79,61 @@ impl Hash for StructureKey {
     }
 }
 
-#[derive(Debug, Default, Clone, PartialEq)]
+/// Trees planned together share what's left of their stockpile. A clone gets its own copy of
+/// what's left, so changing it can't affect the original, and [`StructureTree::clone_all`] keeps
+/// clones of trees planned together sharing with each other.
+#[derive(Debug, Default, PartialEq)]
 pub struct StructureTree {
     pub arena: Arena<StructureTreeNode>,
     pub roots: Option<Vec<NodeId>>,
+    /// Nodes that are alternatives to each other. Each node holds the index of its group.
+    groups: Vec<Vec<NodeId>>,
+    target: Option<Output>,
+    user_inputs: HashSet<Material>,
+    stockpile_draws: Vec<StockpileDraw>,
+    /// Draws given back when an alternative was deactivated, keyed by the alternative. They're
+    /// taken again if it's reactivated.
+    released_draws: HashMap<NodeId, Vec<StockpileDraw>>,
+    /// Graph and stockpile used to expand alternatives on activation.
+    graph: Option<ResourceGraph>,
+    supply: SharedSupply,
 }
 
 impl StructureTree {
+    /// Switch to an alternative. Stock drawn by the alternative it replaces, and everything
+    /// below it, goes back to the stockpile before the new one draws from it.
     pub fn activate_node(&mut self, node_id: NodeId) {
-        let upgrade_options = {
-            let node = self.arena.get(node_id).expect("Node should exist").get();
-            // Rc clone is a little sloppy here, but avoids carrying a reference on self
-            node.upgrade_options.clone()
-        };
-        let upgrade_options = upgrade_options.borrow_mut();
-        for option_node_id in upgrade_options.iter() {
+        let group = self
+            .arena
+            .get(node_id)
+            .expect("Node should exist")
+            .get()
+            .group;
+        let previous = self.groups[group].iter().copied().find(|option_node_id| {
+            self.get_node(*option_node_id)
+                .expect("Node should exist")
+                .active
+        });
+        if previous == Some(node_id) {
+            return;
+        }
+
+        for option_node_id in &self.groups[group] {
             let node = self
                 .arena
                 .get_mut(*option_node_id)
                 .expect("Node should exist")
                 .get_mut();
 
-            if *option_node_id == node_id {
-                node.active = true;
-            } else {
-                node.active = false;
-            }
+            node.active = *option_node_id == node_id;
+        }
+        if let Some(previous) = previous {
+            self.release_draws(previous);
+        }
+
+        if let Some(graph) = self.graph.take() {
+            graph.expand_node(self, node_id);
+            self.graph = Some(graph);
         }
     }
 
@@ -81,13 +141,87 @@ impl StructureTree {
         self.arena.get(node_id).map(|node| node.get())
     }
 
+    /// Every alternative to a node, including the node itself.
+    pub fn options(&self, node: &StructureTreeNode) -> &[NodeId] {
+        &self.groups[node.group]
+    }
+
     pub fn get_arena_node(&self, node_id: NodeId) -> Option<&Node<StructureTreeNode>> {
         self.arena.get(node_id)
     }
 
-    pub fn traverse(&self) -> StructureTreeTraversal {
+    pub fn traverse(&self) -> StructureTreeTraversal<'_> {
         StructureTreeTraversal::new(self)
     }
+
+    /// Material and hourly rate the tree was calculated for.
+    pub fn target(&self) -> Option<&Output> {
+        self.target.as_ref()
+    }
+
+    /// Inputs brought in from outside the factory when the tree was calculated.
+    pub fn user_inputs(&self) -> &HashSet<Material> {
+        &self.user_inputs
+    }
+
+    /// Materials taken from the stockpile by the active nodes.
+    pub fn stockpile_draws(&self) -> &[StockpileDraw] {
+        &self.stockpile_draws
+    }
+
+    /// Return the draws of a node and everything below it to the stockpile.
+    fn release_draws(&mut self, node_id: NodeId) {
+        let subtree: HashSet<NodeId> = node_id.descendants(&self.arena).collect();
+        let (released, kept): (Vec<StockpileDraw>, Vec<StockpileDraw>) =
+            std::mem::take(&mut self.stockpile_draws)
+                .into_iter()
+                .partition(|draw| draw.consumer.is_some_and(|id| subtree.contains(&id)));
+        self.stockpile_draws = kept;
+
+        let mut supply = self.supply.lock();
+        for draw in &released {
+            supply.release(draw);
+        }
+        drop(supply);
+
+        self.released_draws.insert(node_id, released);
+    }
+}
+
+impl Clone for StructureTree {
+    fn clone(&self) -> Self {
+        Self {
+            arena: self.arena.clone(),
+            roots: self.roots.clone(),
+            groups: self.groups.clone(),
+            target: self.target.clone(),
+            user_inputs: self.user_inputs.clone(),
+            stockpile_draws: self.stockpile_draws.clone(),
+            released_draws: self.released_draws.clone(),
+            graph: self.graph.clone(),
+            supply: self.supply.detach(),
+        }
+    }
+}
+
+impl StructureTree {
+    /// Clone a set of trees. Clones of trees that shared a stockpile share one copy of it, apart
+    /// from the originals.
+    pub fn clone_all(trees: &[StructureTree]) -> Vec<StructureTree> {
+        let mut clones: Vec<StructureTree> = Vec::with_capacity(trees.len());
+        for (idx, tree) in trees.iter().enumerate() {
+            let mut clone = tree.clone();
+            let earlier = trees[..idx]
+                .iter()
+                .position(|earlier| earlier.supply.is_shared_with(&tree.supply));
+            if let Some(earlier) = earlier {
+                clone.supply = clones[earlier].supply.share();
+            }
+            clones.push(clone);
+        }
+
+        clones
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -95,7 +229,9 @@ pub struct StructureTreeNode {
     structure: StructureKey,
     count: f32,
     active: bool,
-    upgrade_options: Rc<RefCell<Vec<NodeId>>>,
+    /// Inactive alternatives only get children once they're activated.
+    expanded: bool,
+    group: usize,
     output: Output,
 }
 
@@ -112,10 +248,6 @@ impl StructureTreeNode {
         self.active
     }
 
-    pub fn options(&self) -> Rc<RefCell<Vec<NodeId>>> {
-        self.upgrade_options.clone()
-    }
-
     pub fn output(&self) -> String {
         format!("{} - {}", self.output.material, self.output.value)
     }
@@ -150,18 +282,16 @@ impl<'a> Iterator for StructureTreeTraversal<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         let node_id = self.stack.pop();
 
-        node_id
-            .map(|node_id| {
-                for child in node_id.children(&self.arena) {
-                    let node = self.arena.get(child).expect("Node should exist").get();
-                    if node.is_active() {
-                        self.stack.push(child);
-                    }
+        node_id.and_then(|node_id| {
+            for child in node_id.children(self.arena) {
+                let node = self.arena.get(child).expect("Node should exist").get();
+                if node.is_active() {
+                    self.stack.push(child);
                 }
+            }
 
-                self.arena.get(node_id).map(|node_id| node_id.get())
-            })
-            .flatten()
+            self.arena.get(node_id).map(|node_id| node_id.get())
+        })
     }
 }
 
@@ -196,40 +326,307 @@ impl Ord for FactoryRequirementsBuilding {
     }
 }
 
-#[derive(Debug, Serialize, PartialEq)]
+/// Buildings running a single production channel.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct FactoryRequirementsChannel {
+    pub building: String,
+    pub upgrade: Option<String>,
+    pub channel: usize,
+    pub count: f32,
+}
+
+impl Eq for FactoryRequirementsChannel {}
+
+impl PartialOrd for FactoryRequirementsChannel {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FactoryRequirementsChannel {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.building
+            .cmp(&other.building)
+            .then(self.upgrade.cmp(&other.upgrade))
+            .then(self.channel.cmp(&other.channel))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
 pub struct FactoryRequirements {
     pub buildings: Vec<FactoryRequirementsBuilding>,
+    /// Buildings split by the channel they run.
+    pub channels: Vec<FactoryRequirementsChannel>,
+    /// Power and build cost round every node up to whole buildings. Merged and scaled plans only
+    /// know channel totals, so they round once per channel, see
+    /// [`ResourceGraph::merge_requirements`].
     pub power: f32,
     pub build_cost: HashMap<Material, u64>,
     pub inputs: HashMap<Material, f32>,
+    pub stockpile_usage: Vec<StockpileUsage>,
+    /// One row per material flowing through the plan, in material order.
+    pub balance: Vec<MaterialBalance>,
+}
+
+/// Structure data shared by every [`ResourceGraph`] planning against it.
+struct Catalog {
+    structure_map: HashMap<String, Structure>,
+    upgrade_map: HashMap<Material, Vec<Upgrade>>,
+    /// Upgrades with a production channel consuming each material.
+    consumer_map: HashMap<Material, Vec<Upgrade>>,
 }
 
-pub struct ResourceGraph<'a> {
-    structure_map: &'a HashMap<String, &'a Structure>,
-    upgrade_map: &'a HashMap<Material, Vec<Upgrade>>,
+lazy_static::lazy_static! {
+    static ref CATALOG: Arc<Catalog> = Arc::new(Catalog {
+        structure_map: STRUCTURE_MAP
+            .iter()
+            .map(|(name, structure)| (name.clone(), (*structure).clone()))
+            .collect(),
+        upgrade_map: OUTPUT_MAP.clone(),
+        consumer_map: INPUT_MAP.clone(),
+    });
 }
 
-impl<'a> Default for ResourceGraph<'a> {
+/// Cheap to clone, and can be shared between threads.
+#[derive(Clone)]
+pub struct ResourceGraph {
+    catalog: Arc<Catalog>,
+    preferences: RecipePreferences,
+    /// Production options for every material, worked out once per set of preferences.
+    options: Arc<HashMap<Material, Vec<StructureKey>>>,
+    /// Subtrees planned at one unit an hour, scaled wherever their material is needed again.
+    /// Inactive alternatives in them aren't expanded until they're activated.
+    subtrees: SubtreeCache,
+}
+
+impl Default for ResourceGraph {
     fn default() -> Self {
-        Self {
-            structure_map: &*STRUCTURE_MAP,
-            upgrade_map: &*OUTPUT_MAP,
-        }
+        Self::from_catalog(CATALOG.clone())
     }
 }
 
-impl<'a> ResourceGraph<'a> {
+impl fmt::Debug for ResourceGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResourceGraph")
+            .field("preferences", &self.preferences)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for ResourceGraph {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.catalog, &other.catalog) && self.preferences == other.preferences
+    }
+}
+
+impl ResourceGraph {
     #[cfg(test)]
     fn new(
-        structure_map: &'a HashMap<String, &'a Structure>,
-        upgrade_map: &'a HashMap<Material, Vec<Upgrade>>,
+        structure_map: &HashMap<String, &Structure>,
+        upgrade_map: &HashMap<Material, Vec<Upgrade>>,
     ) -> Self {
-        Self {
+        let structure_map = structure_map
+            .iter()
+            .map(|(name, structure)| (name.clone(), (*structure).clone()))
+            .collect();
+
+        let consumer_map = test::consumer_map(&structure_map);
+
+        Self::from_catalog(Arc::new(Catalog {
             structure_map,
-            upgrade_map,
+            upgrade_map: upgrade_map.clone(),
+            consumer_map,
+        }))
+    }
+
+    fn from_catalog(catalog: Arc<Catalog>) -> Self {
+        let mut graph = Self {
+            catalog,
+            preferences: RecipePreferences::default(),
+            options: Arc::default(),
+            subtrees: SubtreeCache::default(),
+        };
+        graph.cache_options();
+
+        graph
+    }
+
+    /// Use the given preferences when choosing between alternatives in every tree.
+    pub fn with_preferences(mut self, preferences: RecipePreferences) -> Self {
+        self.preferences = preferences;
+        self.cache_options();
+
+        self
+    }
+
+    pub fn preferences(&self) -> &RecipePreferences {
+        &self.preferences
+    }
+
+    fn structure(&self, key: &StructureKey) -> &Structure {
+        let name = key.parent.as_ref().unwrap_or(&key.upgrade);
+
+        self.catalog
+            .structure_map
+            .get(name)
+            .expect("Structure should exist")
+    }
+
+    fn upgrade(&self, key: &StructureKey) -> &Upgrade {
+        let structure = self.structure(key);
+        if key.parent.is_some() {
+            structure
+                .upgrades
+                .get(&key.upgrade)
+                .expect("Upgrade should exist")
+        } else {
+            &structure.default_upgrade
         }
     }
 
+    fn production_channel(&self, key: &StructureKey) -> &ProductionChannel {
+        &self.upgrade(key).production_channels[key.prod_channel_idx]
+    }
+
+    /// Power and build cost of the channels, with each channel's count rounded up to whole
+    /// buildings. Buildings only run one channel at a time, so two channels of the same building
+    /// at half a building each still need two buildings, not one.
+    fn channel_costs(
+        &self,
+        channels: &[FactoryRequirementsChannel],
+    ) -> (f32, HashMap<Material, u64>) {
+        let mut power = 0.0;
+        let mut build_costs = HashMap::new();
+        for channel in channels {
+            self.add_costs(
+                &mut power,
+                &mut build_costs,
+                &channel.building,
+                channel.upgrade.as_deref(),
+                channel.channel,
+                channel.count,
+            );
+        }
+
+        (power, build_costs)
+    }
+
+    /// Add the power and build cost of `count` buildings running a channel, rounded up to
+    /// whole buildings.
+    fn add_costs(
+        &self,
+        power: &mut f32,
+        build_costs: &mut HashMap<Material, u64>,
+        building: &str,
+        upgrade: Option<&str>,
+        channel: usize,
+        count: f32,
+    ) {
+        let structure = self
+            .catalog
+            .structure_map
+            .get(building)
+            .expect("Structure should exist");
+        calculate_build_costs(build_costs, &structure.default_upgrade, count);
+
+        let upgrade = match upgrade {
+            Some(upgrade) => {
+                let upgrade = structure
+                    .upgrades
+                    .get(upgrade)
+                    .expect("Upgrade should exist");
+                calculate_build_costs(build_costs, upgrade, count);
+
+                upgrade
+            }
+            None => &structure.default_upgrade,
+        };
+
+        *power += upgrade.production_channels[channel].power * count.ceil();
+    }
+
+    /// Work out the option list of every material for the current preferences, and start a new
+    /// subtree cache for them.
+    fn cache_options(&mut self) {
+        let options = self
+            .catalog
+            .upgrade_map
+            .keys()
+            .map(|material| (*material, self.find_production_options(*material)))
+            .collect();
+
+        self.options = Arc::new(options);
+        self.subtrees = SubtreeCache::default();
+    }
+
+    /// Every allowed way of producing a material. The option to activate comes first, followed
+    /// by the rest from fastest to slowest.
+    fn production_options(&self, material: Material) -> &[StructureKey] {
+        self.options
+            .get(&material)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    fn find_production_options(&self, material: Material) -> Vec<StructureKey> {
+        let mut upgrade_list = Vec::new();
+        for upgrade in self
+            .catalog
+            .upgrade_map
+            .get(&material)
+            .into_iter()
+            .flatten()
+        {
+            for (prod_channel_idx, production_channel) in
+                upgrade.production_channels.iter().enumerate()
+            {
+                // FIXME: This sucks, change outputs to be a map
+                for output in &production_channel.outputs {
+                    if material == output.material {
+                        let structure_key = StructureKey {
+                            parent: upgrade.parent.clone(),
+                            upgrade: upgrade.name.clone(),
+                            prod_channel_idx,
+                            output: output.clone(),
+                        };
+
+                        if self.preferences.is_excluded(&structure_key) {
+                            continue;
+                        }
+
+                        let output_val = production_channel.hourly_rate(output.value);
+                        upgrade_list.push((output_val, structure_key));
+                    }
+                }
+            }
+        }
+
+        // Fastest first. Ties fall back to the building, upgrade and channel so the choice
+        // doesn't depend on the order the structure data was loaded in.
+        upgrade_list.sort_by(|(a, a_key), (b, b_key)| {
+            b.partial_cmp(a)
+                .unwrap()
+                .then_with(|| a_key.building_key().cmp(&b_key.building_key()))
+                .then(a_key.prod_channel_idx.cmp(&b_key.prod_channel_idx))
+        });
+        let mut options: Vec<StructureKey> = upgrade_list
+            .into_iter()
+            .map(|(_, structure_key)| structure_key)
+            .collect();
+
+        if let Some(idx) = self.preferences.choose(material, &options) {
+            let preferred = options.remove(idx);
+            options.insert(0, preferred);
+        }
+
+        options
+    }
+
+    /// Whether a material has to be brought in from outside the factory.
+    fn is_external_input(&self, material: Material, user_inputs: &HashSet<Material>) -> bool {
+        user_inputs.contains(&material) || self.production_options(material).is_empty()
+    }
+
     /// Calculate factory requirements given a material and a rate.
     ///
     /// Rate is assumed to be unit/hour.
@@ -238,12 +635,57 @@ impl<'a> ResourceGraph<'a> {
         outputs: HashMap<Material, u64>,
         user_inputs: HashSet<Material>,
     ) -> Vec<StructureTree> {
-        let mut trees = Vec::new();
+        self.calculate_factory_requirements_with_stockpile(
+            outputs,
+            user_inputs,
+            &Stockpile::default(),
+        )
+    }
 
-        for (output, rate) in outputs.into_iter() {
-            let mut tree = StructureTree::default();
+    /// Calculate factory requirements, covering as much as possible from a stockpile first.
+    ///
+    /// Stockpiled materials are spread evenly over the stockpile's horizon, and production is
+    /// only planned for the shortfall. Targets are planned in material order, so earlier targets
+    /// get first pick of the stockpile. The returned trees keep sharing what's left, so
+    /// alternatives activated later in any of them can't draw the same stock twice.
+    pub fn calculate_factory_requirements_with_stockpile(
+        &self,
+        outputs: HashMap<Material, u64>,
+        user_inputs: HashSet<Material>,
+        stockpile: &Stockpile,
+    ) -> Vec<StructureTree> {
+        self.calculate_factory_requirements_with_supply(
+            outputs,
+            user_inputs,
+            &HashMap::new(),
+            stockpile,
+        )
+    }
+
+    /// Calculate factory requirements, bringing in capped inputs up to their hourly cap and
+    /// producing anything needed beyond it. Capped inputs are drawn on after the stockpile, and
+    /// shared between the trees the same way, but are reported as inputs.
+    pub fn calculate_factory_requirements_with_supply(
+        &self,
+        outputs: HashMap<Material, u64>,
+        user_inputs: HashSet<Material>,
+        capped_inputs: &HashMap<Material, u64>,
+        stockpile: &Stockpile,
+    ) -> Vec<StructureTree> {
+        let mut trees = Vec::new();
+        let supply =
+            SharedSupply::new(StockpileSupply::new(stockpile).with_capped_inputs(capped_inputs));
+
+        for (output, rate) in sorted(outputs) {
+            let mut tree = StructureTree {
+                target: Some(Output::new(output, rate)),
+                user_inputs: user_inputs.clone(),
+                graph: Some(self.clone()),
+                supply: supply.share(),
+                ..Default::default()
+            };
             let mut stack = vec![(output, rate as f32, None)];
-            self.traverse_building_reqs(&mut stack, &user_inputs, &mut tree);
+            self.traverse_building_reqs(&mut stack, &user_inputs, &mut supply.lock(), &mut tree);
             trees.push(tree);
         }
 
@@ -257,12 +699,22 @@ impl<'a> ResourceGraph<'a> {
     ) -> FactoryRequirements {
         let mut build_costs = HashMap::new();
         let mut power = 0.0;
+        let mut channel_counts: HashMap<StructureKey, f32> = HashMap::new();
         let mut inputs = HashMap::new();
+        let mut stockpile_usage = HashMap::new();
         for tree in trees {
+            let mut draws: HashMap<Option<NodeId>, Vec<&StockpileDraw>> = HashMap::new();
+            for draw in &tree.stockpile_draws {
+                draws.entry(draw.consumer).or_default().push(draw);
+            }
+            for draw in draws.get(&None).into_iter().flatten() {
+                stockpile::add_draw(&mut stockpile_usage, &mut inputs, draw, None);
+            }
+
             let roots = if let Some(roots) = &tree.roots {
                 roots
             } else {
-                break;
+                continue;
             };
             for root in roots {
                 let mut stack = vec![*root];
@@ -275,72 +727,42 @@ impl<'a> ResourceGraph<'a> {
                         stack.push(child);
                     }
 
-                    if let Some(parent) = &node.structure.parent {
-                        // Non-default upgrade case
-                        let structure = self
-                            .structure_map
-                            .get(parent)
-                            .expect("Structure should exist");
-                        let upgrade = structure
-                            .upgrades
-                            .get(&node.structure.upgrade)
-                            .expect("Upgrade should exist");
-
-                        calculate_build_costs(
-                            &mut build_costs,
-                            &structure.default_upgrade,
-                            node.count,
-                        );
-                        calculate_build_costs(&mut build_costs, upgrade, node.count);
-
-                        power += upgrade.production_channels[node.structure.prod_channel_idx].power
-                            * node.count.ceil();
-                    } else {
-                        // Default upgrade case
-                        let structure = self
-                            .structure_map
-                            .get(&node.structure.upgrade)
-                            .expect("Structure should exist");
-
-                        calculate_build_costs(
-                            &mut build_costs,
-                            &structure.default_upgrade,
-                            node.count,
-                        );
+                    let (building, upgrade) = node.structure.building_key();
+                    self.add_costs(
+                        &mut power,
+                        &mut build_costs,
+                        &building,
+                        upgrade.as_deref(),
+                        node.structure.prod_channel_idx,
+                        node.count,
+                    );
+                    *channel_counts.entry(node.structure.clone()).or_default() += node.count;
 
-                        let production_channel = &structure.default_upgrade.production_channels
-                            [node.structure.prod_channel_idx];
-                        power += production_channel.power * node.count.ceil();
-                    }
+                    let production_channel = self.production_channel(&node.structure);
 
-                    // Calculate inputs
-                    let upgrade = if let Some(parent) = &node.structure.parent {
-                        let structure = self
-                            .structure_map
-                            .get(parent)
-                            .expect("Structure should exist");
-
-                        structure
-                            .upgrades
-                            .get(&node.structure.upgrade)
-                            .expect("Upgrade should exist")
-                    } else {
-                        let structure = self
-                            .structure_map
-                            .get(&node.structure.upgrade)
-                            .expect("Structure should exist");
-
-                        &structure.default_upgrade
-                    };
-
-                    let production_channel =
-                        &upgrade.production_channels[node.structure.prod_channel_idx];
+                    let node_draws = draws.get(&Some(node_id)).map(Vec::as_slice);
+                    for draw in node_draws.unwrap_or_default() {
+                        stockpile::add_draw(
+                            &mut stockpile_usage,
+                            &mut inputs,
+                            draw,
+                            Some(node.structure_name()),
+                        );
+                    }
 
                     for input in &production_channel.inputs {
-                        if !self.upgrade_map.contains_key(&input.material)
-                            || user_inputs.contains(&input.material)
-                        {
-                            let rate = production_channel.hourly_rate(input.value) * node.count;
+                        if self.is_external_input(input.material, &user_inputs) {
+                            let drawn: f32 = node_draws
+                                .unwrap_or_default()
+                                .iter()
+                                .filter(|draw| draw.material == input.material)
+                                .map(|draw| draw.rate)
+                                .sum();
+                            let rate =
+                                production_channel.hourly_rate(input.value) * node.count - drawn;
+                            if rate <= 0.0 {
+                                continue;
+                            }
                             let entry = inputs.entry(input.material).or_default();
 
                             *entry += rate;
@@ -350,127 +772,132 @@ impl<'a> ResourceGraph<'a> {
             }
         }
 
-        // Dedupe structures
-        let mut building_map = HashMap::new();
-        for tree in trees {
-            for node in tree.traverse() {
-                if !node.active {
-                    continue;
-                }
-                if let Some(parent) = node.structure.parent.clone() {
-                    let entry: &mut f32 = building_map
-                        .entry((parent, Some(node.structure.upgrade.clone())))
-                        .or_default();
-
-                    *entry += node.count;
-                } else {
-                    let entry = building_map
-                        .entry((node.structure.upgrade.clone(), None))
-                        .or_default();
-
-                    *entry += node.count;
+        let mut channels: Vec<FactoryRequirementsChannel> = channel_counts
+            .into_iter()
+            .map(|(structure_key, count)| {
+                let (building, upgrade) = structure_key.building_key();
+                FactoryRequirementsChannel {
+                    building,
+                    upgrade,
+                    channel: structure_key.prod_channel_idx,
+                    count,
                 }
-            }
-        }
-
+            })
+            .collect();
         //Sort here to avoid non-determinism in test and outputs.
-        let buildings: Vec<FactoryRequirementsBuilding> = sorted(building_map.into_iter().map(
-            |((structure, upgrade), count)| FactoryRequirementsBuilding {
-                building: structure,
-                upgrade,
-                count,
-            },
-        ))
-        .collect();
+        channels.sort();
+        let buildings = buildings_from_channels(&channels);
+
+        let stockpile_usage = stockpile::sorted_usage(stockpile_usage);
+        let balance = self.material_balance(trees, &inputs, &stockpile_usage);
 
         FactoryRequirements {
             buildings,
+            channels,
             power,
             build_cost: build_costs,
             inputs,
+            stockpile_usage,
+            balance,
         }
     }
 
+    /// Add the children of an alternative that was created inactive, or take back the stock an
+    /// alternative released when it was deactivated. Anything the stockpile can no longer cover
+    /// is planned as production instead.
+    fn expand_node(&self, tree: &mut StructureTree, node_id: NodeId) {
+        let node = tree
+            .arena
+            .get_mut(node_id)
+            .expect("Node should exist")
+            .get_mut();
+        let mut stack = Vec::new();
+        let supply = tree.supply.share();
+        let mut supply = supply.lock();
+        if node.expanded {
+            for draw in tree.released_draws.remove(&node_id).unwrap_or_default() {
+                let mut shortfall = draw.rate;
+                for redraw in supply.draw(draw.material, draw.rate, draw.consumer) {
+                    shortfall -= redraw.rate;
+                    tree.stockpile_draws.push(redraw);
+                }
+                if shortfall > 0.0 {
+                    stack.push((draw.material, shortfall, draw.consumer));
+                }
+            }
+        } else {
+            node.expanded = true;
+            let structure_key = node.structure.clone();
+            let count = node.count;
+            self.push_inputs(&mut stack, &structure_key, count, node_id);
+        }
+
+        let user_inputs = tree.user_inputs.clone();
+        self.traverse_building_reqs(&mut stack, &user_inputs, &mut supply, tree);
+    }
+
+    /// Expand the inputs on the stack into tree nodes. Each entry is the material, the rate it's
+    /// needed at and the node consuming it.
     fn traverse_building_reqs(
         &self,
         stack: &mut Vec<(Material, f32, Option<NodeId>)>,
         user_inputs: &HashSet<Material>,
+        supply: &mut StockpileSupply,
         tree: &mut StructureTree,
     ) {
-        while let Some((current_input, current_rate, parent_node)) = stack.pop() {
-            if let Some(upgrades) = self.upgrade_map.get(&current_input) {
-                if !user_inputs.contains(&current_input) {
-                    self.calculate_building_counts(
-                        upgrades,
-                        current_input,
-                        current_rate,
-                        stack,
-                        tree,
-                        parent_node,
-                    );
+        while let Some((current_input, mut current_rate, parent_node)) = stack.pop() {
+            let draws = supply.draw(current_input, current_rate, parent_node);
+            if !draws.is_empty() {
+                current_rate -= draws.iter().map(|draw| draw.rate).sum::<f32>();
+                tree.stockpile_draws.extend(draws);
+
+                if current_rate <= 0.0 {
+                    continue;
                 }
             }
+
+            if user_inputs.contains(&current_input)
+                || self.production_options(current_input).is_empty()
+            {
+                continue;
+            }
+
+            // Stock changes the shape of the subtree, so it's planned step by step instead.
+            let subtree = self.subtree(current_input, user_inputs);
+            if subtree.draws_on(supply) {
+                let options = self.production_options(current_input);
+                self.calculate_building_counts(options, current_rate, stack, tree, parent_node);
+            } else {
+                subtree.instantiate(self, current_rate, tree, parent_node);
+            }
         }
     }
 
     fn calculate_building_counts(
         &self,
-        upgrades: &[Upgrade],
-        current_input: Material,
+        options: &[StructureKey],
         current_rate: f32,
         stack: &mut Vec<(Material, f32, Option<NodeId>)>,
         tree: &mut StructureTree,
         parent_node: Option<NodeId>,
     ) {
-        let mut upgrade_list = Vec::new();
-        let upgrade_options = Rc::new(RefCell::new(Vec::new()));
-        for upgrade in upgrades {
-            for (prod_channel_idx, production_channel) in
-                upgrade.production_channels.iter().enumerate()
-            {
-                // FIXME: This sucks, change outputs to be a map
-                for output in &production_channel.outputs {
-                    if current_input == output.material {
-                        let structure_key = StructureKey {
-                            parent: upgrade.parent.clone(),
-                            upgrade: upgrade.name.clone(),
-                            prod_channel_idx,
-                            output: output.clone(),
-                        };
-
-                        let output_val = production_channel.hourly_rate(output.value);
-                        upgrade_list.push((output_val, structure_key));
-                    }
-                }
-            }
-        }
-
-        upgrade_list.sort_by(|(a, _), (b, __)| a.partial_cmp(b).unwrap());
-        let mut upgrade_iter = upgrade_list.into_iter().rev();
-        let structure_key = upgrade_iter.next().unwrap().1;
-        self.push_upgrade_to_tree(
-            current_rate,
-            tree,
-            stack,
-            &parent_node,
-            structure_key,
-            upgrade_options.clone(),
-            true,
-        );
-
-        for (_, structure_key) in upgrade_iter {
+        let group = tree.groups.len();
+        tree.groups.push(Vec::new());
+        for (idx, structure_key) in options.iter().enumerate() {
             self.push_upgrade_to_tree(
                 current_rate,
                 tree,
                 stack,
                 &parent_node,
-                structure_key,
-                upgrade_options.clone(),
-                false,
+                structure_key.clone(),
+                group,
+                idx == 0,
             );
         }
     }
 
+    /// Add a node for one way of producing a material. Only the active option's inputs are
+    /// expanded, the others wait until they're activated.
     #[allow(clippy::too_many_arguments)]
     fn push_upgrade_to_tree(
         &self,
@@ -479,50 +906,28 @@ impl<'a> ResourceGraph<'a> {
         stack: &mut Vec<(Material, f32, Option<NodeId>)>,
         parent_node: &Option<NodeId>,
         structure_key: StructureKey,
-        upgrade_options: Rc<RefCell<Vec<NodeId>>>,
+        group: usize,
         active: bool,
     ) {
-        let production_channel = if let Some(parent) = &structure_key.parent {
-            let structure = self
-                .structure_map
-                .get(parent)
-                .expect("Structure should exist");
-            let upgrade = structure
-                .upgrades
-                .get(&structure_key.upgrade)
-                .expect("Upgrade should exist");
-
-            upgrade.production_channels[structure_key.prod_channel_idx].clone()
-        } else {
-            let structure = self
-                .structure_map
-                .get(&structure_key.upgrade)
-                .expect("Structure should exist");
-
-            structure.default_upgrade.production_channels[structure_key.prod_channel_idx].clone()
-        };
+        let production_channel = self.production_channel(&structure_key);
 
         let output_material = structure_key.output.material;
         let output_value = structure_key.output.value;
         let hourly_rate = production_channel.hourly_rate(output_value);
-        let building_count = current_rate as f32 / hourly_rate;
+        let building_count = current_rate / hourly_rate;
         let node = StructureTreeNode {
             structure: structure_key,
             count: building_count,
             active,
-            upgrade_options,
+            expanded: active,
+            group,
             output: Output::new(
                 output_material,
                 (hourly_rate * building_count).ceil() as u64,
             ),
         };
         let node_id = tree.arena.new_node(node);
-        let node = tree
-            .arena
-            .get_mut(node_id)
-            .expect("Node should exist")
-            .get_mut();
-        node.upgrade_options.borrow_mut().push(node_id);
+        tree.groups[group].push(node_id);
         if let Some(parent_node_id) = parent_node {
             parent_node_id.append(node_id, &mut tree.arena);
         } else {
@@ -530,6 +935,20 @@ impl<'a> ResourceGraph<'a> {
             roots.push(node_id);
         }
 
+        if active {
+            let structure_key = &tree.get_node(node_id).expect("Node should exist").structure;
+            self.push_inputs(stack, structure_key, building_count, node_id);
+        }
+    }
+
+    fn push_inputs(
+        &self,
+        stack: &mut Vec<(Material, f32, Option<NodeId>)>,
+        structure_key: &StructureKey,
+        building_count: f32,
+        node_id: NodeId,
+    ) {
+        let production_channel = self.production_channel(structure_key);
         for input in &production_channel.inputs {
             stack.push((
                 input.material,
@@ -540,6 +959,29 @@ impl<'a> ResourceGraph<'a> {
     }
 }
 
+/// Total count of every building and upgrade, across the channels they run.
+fn buildings_from_channels(
+    channels: &[FactoryRequirementsChannel],
+) -> Vec<FactoryRequirementsBuilding> {
+    let mut building_map: HashMap<(String, Option<String>), f32> = HashMap::new();
+    for channel in channels {
+        *building_map
+            .entry((channel.building.clone(), channel.upgrade.clone()))
+            .or_default() += channel.count;
+    }
+
+    sorted(
+        building_map
+            .into_iter()
+            .map(|((building, upgrade), count)| FactoryRequirementsBuilding {
+                building,
+                upgrade,
+                count,
+            }),
+    )
+    .collect()
+}
+
 fn calculate_build_costs(
     build_costs: &mut HashMap<Material, u64>,
     upgrade: &Upgrade,
@@ -557,7 +999,7 @@ mod test {
     use super::*;
     use foxhole_production_calculator_types::BuildCost;
 
-    fn build_structures() -> Vec<Structure> {
+    pub(crate) fn build_structures() -> Vec<Structure> {
         let upgrade_a = Upgrade::new(
             "upgrade_a".to_string(),
             vec![BuildCost::new(Material::BasicMaterials, 1)],
@@ -615,20 +1057,59 @@ mod test {
         );
 
         let structure_a = Structure::new(
+            Footprint::new(2, 2),
             upgrade_a,
             vec![("upgrade_a_1".to_string(), upgrade_a_1)]
                 .into_iter()
                 .collect(),
         );
 
-        let structure_b = Structure::new(upgrade_b, HashMap::new());
+        let structure_b = Structure::new(Footprint::new(2, 3), upgrade_b, HashMap::new());
 
-        let structure_c = Structure::new(upgrade_c, HashMap::new());
+        let structure_c = Structure::new(Footprint::new(3, 3), upgrade_c, HashMap::new());
 
         vec![structure_a, structure_b, structure_c]
     }
 
-    fn setup_test_structure_maps(
+    fn channel(
+        building: &str,
+        upgrade: Option<&str>,
+        channel: usize,
+        count: f32,
+    ) -> FactoryRequirementsChannel {
+        FactoryRequirementsChannel {
+            building: building.to_string(),
+            upgrade: upgrade.map(str::to_string),
+            channel,
+            count,
+        }
+    }
+
+    /// Index of the upgrades consuming each material, as generated into `INPUT_MAP` for the
+    /// bundled structures.
+    pub(crate) fn consumer_map(
+        structure_map: &HashMap<String, Structure>,
+    ) -> HashMap<Material, Vec<Upgrade>> {
+        let mut consumer_map: HashMap<Material, Vec<Upgrade>> = HashMap::new();
+        for structure in structure_map.values() {
+            for upgrade in
+                std::iter::once(&structure.default_upgrade).chain(structure.upgrades.values())
+            {
+                for production_channel in &upgrade.production_channels {
+                    for input in &production_channel.inputs {
+                        let upgrades = consumer_map.entry(input.material).or_default();
+                        if !upgrades.iter().any(|other| other.name == upgrade.name) {
+                            upgrades.push(upgrade.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        consumer_map
+    }
+
+    pub(crate) fn setup_test_structure_maps(
         structures: &[Structure],
     ) -> (HashMap<String, &Structure>, HashMap<Material, Vec<Upgrade>>) {
         let mut structure_map = HashMap::new();
@@ -678,9 +1159,15 @@ mod test {
         let inputs = vec![(Material::Coal, 5.0)].into_iter().collect();
         let expected_reqs = FactoryRequirements {
             buildings,
+            channels: vec![channel("upgrade_a", Some("upgrade_a_1"), 0, 5.0)],
             power: 5.0,
             build_cost,
             inputs,
+            stockpile_usage: Vec::new(),
+            balance: vec![
+                MaterialBalance::new(Material::Coal, 0.0, 5.0, 5.0, 0.0),
+                MaterialBalance::new(Material::Coke, 10.0, 0.0, 0.0, 10.0),
+            ],
         };
 
         assert_eq!(reqs, expected_reqs);
@@ -720,9 +1207,15 @@ mod test {
         let inputs = vec![(Material::Coal, 10.0)].into_iter().collect();
         let expected_reqs = FactoryRequirements {
             buildings,
+            channels: vec![channel("upgrade_a", None, 0, 10.0)],
             power: 10.0,
             build_cost,
             inputs,
+            stockpile_usage: Vec::new(),
+            balance: vec![
+                MaterialBalance::new(Material::Coal, 0.0, 10.0, 10.0, 0.0),
+                MaterialBalance::new(Material::Coke, 10.0, 0.0, 0.0, 10.0),
+            ],
         };
 
         let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
@@ -751,9 +1244,15 @@ mod test {
         let inputs = vec![(Material::Coal, 5.0)].into_iter().collect();
         let expected_reqs = FactoryRequirements {
             buildings,
+            channels: vec![channel("upgrade_a", Some("upgrade_a_1"), 0, 5.0)],
             power: 5.0,
             build_cost,
             inputs,
+            stockpile_usage: Vec::new(),
+            balance: vec![
+                MaterialBalance::new(Material::Coal, 0.0, 5.0, 5.0, 0.0),
+                MaterialBalance::new(Material::Coke, 10.0, 0.0, 0.0, 10.0),
+            ],
         };
 
         assert_eq!(reqs, expected_reqs);
@@ -792,9 +1291,20 @@ mod test {
             .collect();
         let expected_reqs = FactoryRequirements {
             buildings,
+            channels: vec![
+                channel("upgrade_a", Some("upgrade_a_1"), 0, 5.0),
+                channel("upgrade_b", None, 0, 1.0),
+            ],
             power: 6.0,
             build_cost,
             inputs,
+            stockpile_usage: Vec::new(),
+            balance: vec![
+                MaterialBalance::new(Material::Coal, 0.0, 5.0, 5.0, 0.0),
+                MaterialBalance::new(Material::Coke, 10.0, 0.0, 0.0, 10.0),
+                MaterialBalance::new(Material::Components, 0.0, 1.0, 1.0, 0.0),
+                MaterialBalance::new(Material::Rocket4CFire, 1.0, 0.0, 0.0, 1.0),
+            ],
         };
 
         assert_eq!(reqs, expected_reqs);
@@ -827,9 +1337,19 @@ mod test {
         let inputs = vec![(Material::Components, 2.0)].into_iter().collect();
         let expected_reqs = FactoryRequirements {
             buildings,
+            channels: vec![
+                channel("upgrade_b", None, 0, 1.0),
+                channel("upgrade_b", None, 1, 1.0),
+            ],
             power: 2.0,
             build_cost,
             inputs,
+            stockpile_usage: Vec::new(),
+            balance: vec![
+                MaterialBalance::new(Material::Components, 0.0, 2.0, 2.0, 0.0),
+                MaterialBalance::new(Material::Rocket3CHighExplosive, 1.0, 0.0, 0.0, 1.0),
+                MaterialBalance::new(Material::Rocket4CFire, 1.0, 0.0, 0.0, 1.0),
+            ],
         };
 
         assert_eq!(reqs, expected_reqs);
@@ -863,11 +1383,155 @@ mod test {
         let inputs = vec![(Material::Coal, 0.5)].into_iter().collect();
         let expected_reqs = FactoryRequirements {
             buildings,
+            channels: vec![
+                channel("upgrade_a", Some("upgrade_a_1"), 0, 0.5),
+                channel("upgrade_c", None, 0, 1.0),
+            ],
             power: 2.0,
             build_cost,
             inputs,
+            stockpile_usage: Vec::new(),
+            balance: vec![
+                MaterialBalance::new(Material::Coal, 0.0, 0.5, 0.5, 0.0),
+                MaterialBalance::new(Material::Coke, 1.0, 1.0, 0.0, 0.0),
+                MaterialBalance::new(Material::ConcreteMaterials, 1.0, 0.0, 0.0, 1.0),
+            ],
         };
 
         assert_eq!(reqs, expected_reqs);
     }
+
+    #[test]
+    fn test_alternative_expanded_on_activation() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let stockpile = Stockpile::new(vec![(Material::Coal, 100)].into_iter().collect(), 1.0);
+        let outputs = vec![(Material::Coke, 10)].into_iter().collect();
+        let mut trees =
+            rg.calculate_factory_requirements_with_stockpile(outputs, HashSet::new(), &stockpile);
+
+        // Only the active option has drawn its Coal so far.
+        assert_eq!(trees[0].stockpile_draws().len(), 1);
+        let roots = trees[0].roots.clone().unwrap();
+        let inactive = roots[1];
+        assert!(!trees[0].get_node(inactive).unwrap().expanded);
+
+        trees[0].activate_node(inactive);
+        assert!(trees[0].get_node(inactive).unwrap().expanded);
+        assert_eq!(trees[0].stockpile_draws().len(), 1);
+
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+        assert!(reqs.inputs.is_empty());
+        assert_eq!(
+            reqs.stockpile_usage,
+            vec![StockpileUsage {
+                material: Material::Coal,
+                consumer: Some("upgrade_a".to_string()),
+                rate: 10.0,
+                amount: 10.0,
+            }]
+        );
+
+        // Switching back doesn't expand the first option again, it only takes back its Coal.
+        let children = roots[0].children(&trees[0].arena).count();
+        trees[0].activate_node(roots[0]);
+        assert_eq!(roots[0].children(&trees[0].arena).count(), children);
+        assert_eq!(trees[0].stockpile_draws().len(), 1);
+        assert_eq!(trees[0].stockpile_draws()[0].consumer, Some(roots[0]));
+    }
+
+    #[test]
+    fn test_channel_costs_round_per_channel() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let channels = vec![
+            channel("upgrade_b", None, 0, 0.5),
+            channel("upgrade_b", None, 1, 0.5),
+        ];
+        let (power, build_cost) = rg.channel_costs(&channels);
+
+        assert_eq!(power, 2.0);
+        assert_eq!(
+            build_cost,
+            vec![(Material::BasicMaterials, 2)].into_iter().collect()
+        );
+        assert_eq!(buildings_from_channels(&channels)[0].count, 1.0);
+    }
+
+    #[test]
+    fn test_costs_round_per_node() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        // Half a Coke building in each tree, each rounded up on its own.
+        let outputs = vec![(Material::ConcreteMaterials, 1), (Material::Coke, 1)]
+            .into_iter()
+            .collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+
+        assert_eq!(reqs.power, 3.0);
+        assert_eq!(reqs.build_cost[&Material::BasicMaterials], 5);
+        assert_eq!(
+            reqs.channels,
+            vec![
+                channel("upgrade_a", Some("upgrade_a_1"), 0, 1.0),
+                channel("upgrade_c", None, 0, 1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tied_options_ordered_by_building() {
+        let mut structures = build_structures();
+        let mut structure_d = structures[2].clone();
+        structure_d.default_upgrade.name = "upgrade_d".to_string();
+        structures.push(structure_d);
+
+        for structures in [structures.clone(), structures.into_iter().rev().collect()] {
+            let (structure_map, output_map) = setup_test_structure_maps(&structures);
+            let rg = ResourceGraph::new(&structure_map, &output_map);
+
+            let options: Vec<String> = rg
+                .production_options(Material::ConcreteMaterials)
+                .iter()
+                .map(StructureKey::structure_name)
+                .collect();
+            assert_eq!(options, vec!["upgrade_c", "upgrade_d"]);
+        }
+    }
+
+    #[test]
+    fn test_plan_across_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<ResourceGraph>();
+        assert_send_sync::<StructureTree>();
+        assert_send_sync::<FactoryRequirements>();
+
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let handles: Vec<_> = (1..=4)
+            .map(|rate| {
+                let rg = rg.clone();
+                std::thread::spawn(move || {
+                    let outputs = vec![(Material::Coke, rate * 2)].into_iter().collect();
+                    let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+
+                    rg.factory_requirements_from_trees(&trees, HashSet::new())
+                })
+            })
+            .collect();
+
+        for (rate, handle) in (1..=4).zip(handles) {
+            assert_eq!(handle.join().unwrap().buildings[0].count, rate as f32);
+        }
+    }
 }
@@ -0,0 +1,304 @@
+//! Plan files: everything needed to recalculate a factory, in a form that can be kept under
+//! version control and resolved again after the structure data changes.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+use foxhole_production_calculator_types::Material;
+use serde::{Deserialize, Serialize};
+
+use crate::preferences::RecipePreferences;
+use crate::saved::Selection;
+use crate::stockpile::Stockpile;
+use crate::{ResourceGraph, StructureTree};
+
+/// Version written by [`Plan::new`]. Bumped whenever the plan format changes.
+pub const PLAN_VERSION: u32 = 1;
+
+/// Targets, supplied inputs and choices for a factory, as read from and written to plan files.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Plan {
+    pub version: u32,
+    /// Materials brought in from outside the factory, as much as is needed.
+    #[serde(default)]
+    pub inputs: BTreeSet<Material>,
+    /// Materials to produce. [Unit/Hour]
+    pub targets: BTreeMap<Material, u64>,
+    /// Materials brought in from outside the factory up to a rate. Anything needed beyond that
+    /// is produced. [Unit/Hour]
+    #[serde(default)]
+    pub capped_inputs: BTreeMap<Material, u64>,
+    #[serde(default)]
+    pub preferences: RecipePreferences,
+    /// Alternatives chosen in the targets' trees. Each path starts at the target's material.
+    #[serde(default)]
+    pub selections: Vec<Selection>,
+}
+
+/// A plan calculated against the current structure data.
+#[derive(Debug, PartialEq)]
+pub struct ResolvedPlan {
+    /// The plan with every choice made while resolving it, ready to be written back out.
+    pub plan: Plan,
+    /// Graph with the plan's preferences applied. Anything done with the trees afterwards, from
+    /// working out requirements to recalculating them, should use this rather than the graph the
+    /// plan was resolved with.
+    pub graph: ResourceGraph,
+    pub trees: Vec<StructureTree>,
+    /// Selections for steps or alternatives that no longer exist. These are left at the default
+    /// choice, and are dropped from [`ResolvedPlan::plan`].
+    pub stale: Vec<Selection>,
+}
+
+impl Clone for ResolvedPlan {
+    fn clone(&self) -> Self {
+        Self {
+            plan: self.plan.clone(),
+            graph: self.graph.clone(),
+            trees: StructureTree::clone_all(&self.trees),
+            stale: self.stale.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlanError {
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::UnsupportedVersion(version) => write!(
+                f,
+                "Plan version {} isn't supported, expected {}",
+                version, PLAN_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+impl Plan {
+    pub fn new(targets: BTreeMap<Material, u64>) -> Self {
+        Self {
+            version: PLAN_VERSION,
+            inputs: BTreeSet::new(),
+            targets,
+            capped_inputs: BTreeMap::new(),
+            preferences: RecipePreferences::default(),
+            selections: Vec::new(),
+        }
+    }
+
+    /// Capture the targets, inputs and chosen alternatives of calculated trees.
+    pub fn from_trees(trees: &[StructureTree]) -> Self {
+        let mut plan = Self::new(BTreeMap::new());
+        for tree in trees {
+            if let Some(target) = tree.target() {
+                *plan.targets.entry(target.material).or_default() += target.value;
+            }
+            plan.inputs.extend(tree.user_inputs());
+            if let Some(saved) = tree.save() {
+                plan.selections.extend(saved.selections);
+            }
+        }
+        plan.selections.sort_by(|a, b| a.path.cmp(&b.path));
+
+        plan
+    }
+
+    /// This plan with its targets, inputs and chosen alternatives taken from `trees`. The
+    /// preferences and capped inputs are kept.
+    pub fn with_trees(&self, trees: &[StructureTree]) -> Self {
+        let mut plan = Self::from_trees(trees);
+        plan.capped_inputs = self.capped_inputs.clone();
+        plan.preferences = self.preferences.clone();
+
+        plan
+    }
+
+    /// The capped inputs, ready to plan with
+    /// [`ResourceGraph::calculate_factory_requirements_with_supply`].
+    pub fn capped_inputs(&self) -> HashMap<Material, u64> {
+        self.capped_inputs
+            .iter()
+            .map(|(material, cap)| (*material, *cap))
+            .collect()
+    }
+}
+
+impl ResourceGraph {
+    /// Calculate a plan using its own preferences, then reapply its chosen alternatives.
+    pub fn resolve_plan(&self, plan: &Plan) -> Result<ResolvedPlan, PlanError> {
+        if plan.version != PLAN_VERSION {
+            return Err(PlanError::UnsupportedVersion(plan.version));
+        }
+
+        let graph = self.clone().with_preferences(plan.preferences.clone());
+        let mut trees = graph.calculate_factory_requirements_with_supply(
+            plan.targets
+                .iter()
+                .map(|(material, rate)| (*material, *rate))
+                .collect(),
+            plan.inputs.iter().copied().collect(),
+            &plan.capped_inputs(),
+            &Stockpile::default(),
+        );
+
+        let mut stale = Vec::new();
+        for selection in &plan.selections {
+            let matched = trees.iter().any(|tree| {
+                tree.target().map(|target| target.material) == selection.path.first().copied()
+            });
+            if !matched {
+                stale.push(selection.clone());
+            }
+        }
+        for tree in &mut trees {
+            let material = match tree.target() {
+                Some(target) => target.material,
+                None => continue,
+            };
+            let selections: Vec<Selection> = plan
+                .selections
+                .iter()
+                .filter(|selection| selection.path.first() == Some(&material))
+                .cloned()
+                .collect();
+            stale.extend(tree.apply_selections(&selections));
+        }
+
+        Ok(ResolvedPlan {
+            plan: plan.with_trees(&trees),
+            graph,
+            trees,
+            stale,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::balance::MaterialBalance;
+    use crate::preferences::{ChannelRef, UpgradeRef};
+    use crate::test::{build_structures, setup_test_structure_maps};
+
+    #[test]
+    fn test_resolve_plan() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let mut plan = Plan::new(vec![(Material::ConcreteMaterials, 2)].into_iter().collect());
+        plan.selections.push(Selection {
+            path: vec![Material::ConcreteMaterials, Material::Coke],
+            choice: ChannelRef::new(UpgradeRef::new("upgrade_a", None), 0),
+        });
+        plan.selections.push(Selection {
+            path: vec![Material::Pipe],
+            choice: ChannelRef::new(UpgradeRef::new("upgrade_a", None), 0),
+        });
+
+        let resolved = rg.resolve_plan(&plan).unwrap();
+        let reqs = rg.factory_requirements_from_trees(&resolved.trees, HashSet::new());
+
+        assert_eq!(reqs.buildings[0].building, "upgrade_a");
+        assert_eq!(reqs.buildings[0].upgrade, None);
+        assert_eq!(reqs.buildings[0].count, 2.0);
+        assert_eq!(resolved.stale.len(), 1);
+        assert_eq!(resolved.stale[0].path, vec![Material::Pipe]);
+        assert_eq!(resolved.plan.selections, plan.selections[..1].to_vec());
+    }
+
+    #[test]
+    fn test_capped_inputs() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let mut plan = Plan::new(
+            vec![(Material::ConcreteMaterials, 12)]
+                .into_iter()
+                .collect(),
+        );
+        plan.capped_inputs.insert(Material::Coke, 10);
+
+        let resolved = rg.resolve_plan(&plan).unwrap();
+        let reqs = rg.factory_requirements_from_trees(&resolved.trees, HashSet::new());
+
+        // 10 Coke comes in under the cap, the other 2 are made from Coal.
+        assert!(reqs.stockpile_usage.is_empty());
+        assert_eq!(reqs.inputs[&Material::Coke], 10.0);
+        assert_eq!(reqs.inputs[&Material::Coal], 1.0);
+        assert_eq!(
+            reqs.buildings
+                .iter()
+                .find(|building| building.upgrade.as_deref() == Some("upgrade_a_1"))
+                .unwrap()
+                .count,
+            1.0
+        );
+        assert_eq!(
+            reqs.balance
+                .iter()
+                .find(|row| row.material == Material::Coke)
+                .unwrap(),
+            &MaterialBalance::new(Material::Coke, 2.0, 12.0, 10.0, 0.0)
+        );
+        assert_eq!(resolved.plan.capped_inputs, plan.capped_inputs);
+
+        // Switching to the slower Coke building still only takes up to the cap.
+        let mut trees = resolved.trees;
+        let concrete = trees[0].roots.clone().unwrap()[0];
+        let slower = concrete.children(&trees[0].arena).nth(1).unwrap();
+        trees[0].activate_node(slower);
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+        assert_eq!(reqs.inputs[&Material::Coke], 10.0);
+        assert_eq!(reqs.inputs[&Material::Coal], 2.0);
+    }
+
+    #[test]
+    fn test_toml_round_trip() {
+        let mut plan = Plan::new(vec![(Material::Pipe, 100)].into_iter().collect());
+        plan.inputs.insert(Material::Components);
+        plan.capped_inputs.insert(Material::Coke, 500);
+        plan.preferences = RecipePreferences::default()
+            .exclude_structure("upgrade_b")
+            .prefer(
+                Material::Coke,
+                UpgradeRef::new("upgrade_a", Some("upgrade_a_1")),
+            )
+            .pin(
+                Material::Coal,
+                ChannelRef::new(UpgradeRef::new("upgrade_c", None), 0),
+            );
+        plan.selections.push(Selection {
+            path: vec![Material::Pipe, Material::Coke],
+            choice: ChannelRef::new(UpgradeRef::new("upgrade_a", None), 0),
+        });
+
+        let toml = toml::to_string(&plan).unwrap();
+        assert!(toml.starts_with("version = 1\ninputs = [\"Components\"]\n"));
+        assert_eq!(toml::from_str::<Plan>(&toml).unwrap(), plan);
+    }
+
+    #[test]
+    fn test_plan_version() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let json = r#"{"version": 2, "targets": {"Coke": 1}}"#;
+        let plan: Plan = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            rg.resolve_plan(&plan),
+            Err(PlanError::UnsupportedVersion(2))
+        );
+    }
+}
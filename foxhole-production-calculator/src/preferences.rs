@@ -0,0 +1,198 @@
+//! Plan wide choices between alternative recipes.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use foxhole_production_calculator_types::Material;
+use serde::{Deserialize, Serialize};
+
+use crate::StructureKey;
+
+/// A structure upgrade. `upgrade` is `None` for the structure's default upgrade.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct UpgradeRef {
+    pub building: String,
+    pub upgrade: Option<String>,
+}
+
+impl UpgradeRef {
+    pub fn new(building: &str, upgrade: Option<&str>) -> Self {
+        Self {
+            building: building.to_string(),
+            upgrade: upgrade.map(str::to_string),
+        }
+    }
+
+    fn matches(&self, key: &StructureKey) -> bool {
+        let (building, upgrade) = key.building_key();
+
+        self.building == building && self.upgrade == upgrade
+    }
+}
+
+/// A single production channel of a structure upgrade.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ChannelRef {
+    #[serde(flatten)]
+    pub upgrade: UpgradeRef,
+    pub channel: usize,
+}
+
+impl ChannelRef {
+    pub fn new(upgrade: UpgradeRef, channel: usize) -> Self {
+        Self { upgrade, channel }
+    }
+
+    pub(crate) fn from_key(key: &StructureKey) -> Self {
+        let (building, upgrade) = key.building_key();
+
+        Self::new(UpgradeRef { building, upgrade }, key.prod_channel_idx)
+    }
+
+    pub(crate) fn matches(&self, key: &StructureKey) -> bool {
+        self.upgrade.matches(key) && self.channel == key.prod_channel_idx
+    }
+}
+
+/// Choices applied whenever a material has more than one way of being produced.
+///
+/// Excluded structures and upgrades are never used. If that leaves no way of producing a
+/// material, it's treated as an input from outside the factory. Pinned channels take priority
+/// over preferred upgrades, which take priority over the fastest option. Alternatives that
+/// aren't excluded stay available through [`crate::StructureTree::activate_node`].
+///
+/// Everything is kept in order, so saved preferences are written the same way every time.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RecipePreferences {
+    /// Structures excluded along with all of their upgrades.
+    #[serde(default)]
+    pub excluded_structures: BTreeSet<String>,
+    #[serde(default)]
+    pub preferred: BTreeMap<Material, UpgradeRef>,
+    #[serde(default)]
+    pub pinned: BTreeMap<Material, ChannelRef>,
+    #[serde(default)]
+    pub excluded_upgrades: BTreeSet<UpgradeRef>,
+}
+
+impl RecipePreferences {
+    pub fn prefer(mut self, material: Material, upgrade: UpgradeRef) -> Self {
+        self.preferred.insert(material, upgrade);
+
+        self
+    }
+
+    pub fn pin(mut self, material: Material, channel: ChannelRef) -> Self {
+        self.pinned.insert(material, channel);
+
+        self
+    }
+
+    pub fn exclude_structure(mut self, building: &str) -> Self {
+        self.excluded_structures.insert(building.to_string());
+
+        self
+    }
+
+    pub fn exclude_upgrade(mut self, upgrade: UpgradeRef) -> Self {
+        self.excluded_upgrades.insert(upgrade);
+
+        self
+    }
+
+    pub(crate) fn is_excluded(&self, key: &StructureKey) -> bool {
+        let (building, _) = key.building_key();
+
+        self.excluded_structures.contains(&building)
+            || self
+                .excluded_upgrades
+                .iter()
+                .any(|upgrade| upgrade.matches(key))
+    }
+
+    /// Index of the option to activate, if the preferences pick one.
+    pub(crate) fn choose(&self, material: Material, options: &[StructureKey]) -> Option<usize> {
+        if let Some(pinned) = self.pinned.get(&material) {
+            if let Some(idx) = options.iter().position(|key| pinned.matches(key)) {
+                return Some(idx);
+            }
+        }
+
+        self.preferred
+            .get(&material)
+            .and_then(|preferred| options.iter().position(|key| preferred.matches(key)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::test::{build_structures, setup_test_structure_maps};
+    use crate::{FactoryRequirementsBuilding, ResourceGraph};
+
+    #[test]
+    fn test_exclude_upgrade() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let preferences = RecipePreferences::default()
+            .exclude_upgrade(UpgradeRef::new("upgrade_a", Some("upgrade_a_1")));
+        let rg = ResourceGraph::new(&structure_map, &output_map).with_preferences(preferences);
+
+        let outputs = vec![(Material::Coke, 10)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+
+        assert_eq!(
+            reqs.buildings,
+            vec![FactoryRequirementsBuilding {
+                building: "upgrade_a".to_string(),
+                upgrade: None,
+                count: 10.0,
+            }]
+        );
+        assert_eq!(trees[0].roots.as_ref().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_exclude_structure_makes_input_external() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let preferences = RecipePreferences::default().exclude_structure("upgrade_a");
+        let rg = ResourceGraph::new(&structure_map, &output_map).with_preferences(preferences);
+
+        let outputs = vec![(Material::ConcreteMaterials, 1)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+
+        assert_eq!(reqs.buildings.len(), 1);
+        assert_eq!(
+            reqs.inputs,
+            vec![(Material::Coke, 1.0)].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn test_pin_overrides_preference() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let preferences = RecipePreferences::default()
+            .prefer(
+                Material::Coke,
+                UpgradeRef::new("upgrade_a", Some("upgrade_a_1")),
+            )
+            .pin(
+                Material::Coke,
+                ChannelRef::new(UpgradeRef::new("upgrade_a", None), 0),
+            );
+        let rg = ResourceGraph::new(&structure_map, &output_map).with_preferences(preferences);
+
+        let outputs = vec![(Material::Coke, 10)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+
+        assert_eq!(reqs.buildings[0].upgrade, None);
+        // The faster upgrade is still there to switch to.
+        assert_eq!(trees[0].roots.as_ref().unwrap().len(), 2);
+    }
+}
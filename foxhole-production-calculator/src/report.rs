@@ -0,0 +1,271 @@
+//! Human readable reports of a plan, as Markdown or aligned plain text.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use indextree::NodeId;
+
+use crate::{FactoryRequirements, ResourceGraph, StructureTree};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Markdown,
+    Text,
+}
+
+/// A table with a header row. Numeric columns are right aligned in plain text.
+struct Table {
+    headers: Vec<&'static str>,
+    numeric: Vec<bool>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    fn new(headers: Vec<&'static str>, numeric: Vec<bool>) -> Self {
+        Self {
+            headers,
+            numeric,
+            rows: Vec::new(),
+        }
+    }
+
+    fn render(&self, out: &mut String, format: ReportFormat) {
+        match format {
+            ReportFormat::Markdown => {
+                writeln!(out, "| {} |", self.headers.join(" | ")).unwrap();
+                let separators: Vec<&str> = self
+                    .numeric
+                    .iter()
+                    .map(|numeric| if *numeric { "---:" } else { "---" })
+                    .collect();
+                writeln!(out, "| {} |", separators.join(" | ")).unwrap();
+                for row in &self.rows {
+                    writeln!(out, "| {} |", row.join(" | ")).unwrap();
+                }
+            }
+            ReportFormat::Text => {
+                let mut widths: Vec<usize> = self.headers.iter().map(|h| h.len()).collect();
+                for row in &self.rows {
+                    for (width, cell) in widths.iter_mut().zip(row) {
+                        *width = (*width).max(cell.chars().count());
+                    }
+                }
+
+                let headers: Vec<String> = self.headers.iter().map(|h| h.to_string()).collect();
+                self.text_row(out, &headers, &widths);
+                let rule: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+                self.text_row(out, &rule, &widths);
+                for row in &self.rows {
+                    self.text_row(out, row, &widths);
+                }
+            }
+        }
+    }
+
+    fn text_row(&self, out: &mut String, cells: &[String], widths: &[usize]) {
+        let cells: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .zip(&self.numeric)
+            .map(|((cell, width), numeric)| {
+                if *numeric {
+                    format!("{:>width$}", cell, width = width)
+                } else {
+                    format!("{:<width$}", cell, width = width)
+                }
+            })
+            .collect();
+        writeln!(out, "{}", cells.join("  ").trim_end()).unwrap();
+    }
+}
+
+impl ResourceGraph {
+    /// Render the requirements worked out from `trees`, followed by the production chain of
+    /// every tree.
+    pub fn report(
+        &self,
+        trees: &[StructureTree],
+        reqs: &FactoryRequirements,
+        format: ReportFormat,
+    ) -> String {
+        let mut out = String::new();
+
+        heading(&mut out, "Buildings", format);
+        let mut buildings = Table::new(
+            vec!["Building", "Upgrade", "Count"],
+            vec![false, false, true],
+        );
+        for building in &reqs.buildings {
+            buildings.rows.push(vec![
+                building.building.clone(),
+                building.upgrade.clone().unwrap_or_else(|| "-".to_string()),
+                format!("{:.2}", building.count),
+            ]);
+        }
+        buildings.render(&mut out, format);
+
+        heading(&mut out, "Inputs", format);
+        let mut inputs = Table::new(vec!["Material", "Rate (/h)"], vec![false, true]);
+        let ordered: BTreeMap<_, _> = reqs.inputs.iter().collect();
+        for (material, rate) in ordered {
+            inputs
+                .rows
+                .push(vec![material.to_string(), format!("{:.2}", rate)]);
+        }
+        inputs.render(&mut out, format);
+
+        heading(&mut out, "Power", format);
+        writeln!(out, "{:.2} MW", reqs.power).unwrap();
+
+        heading(&mut out, "Build Cost", format);
+        let mut build_cost = Table::new(vec!["Material", "Amount"], vec![false, true]);
+        let ordered: BTreeMap<_, _> = reqs.build_cost.iter().collect();
+        for (material, amount) in ordered {
+            build_cost
+                .rows
+                .push(vec![material.to_string(), amount.to_string()]);
+        }
+        build_cost.render(&mut out, format);
+
+        heading(&mut out, "Production Chain", format);
+        for tree in trees {
+            for node_id in active_chain(tree) {
+                let details = self.node_details(tree, node_id).expect("Node should exist");
+                let building = match &details.upgrade {
+                    Some(upgrade) => format!("{} ({})", details.building, upgrade),
+                    None => details.building.clone(),
+                };
+                let indent = "  ".repeat(details.depth);
+                let bullet = match format {
+                    ReportFormat::Markdown => "- ",
+                    ReportFormat::Text => "",
+                };
+                writeln!(
+                    out,
+                    "{}{}{:.2}x {} -> {} {:.2}/h",
+                    indent,
+                    bullet,
+                    details.count,
+                    building,
+                    details.output.material,
+                    details.output.rate
+                )
+                .unwrap();
+            }
+        }
+
+        out
+    }
+}
+
+fn heading(out: &mut String, title: &str, format: ReportFormat) {
+    if !out.is_empty() {
+        writeln!(out).unwrap();
+    }
+
+    match format {
+        ReportFormat::Markdown => writeln!(out, "## {}\n", title).unwrap(),
+        ReportFormat::Text => writeln!(out, "{}\n{}", title, "=".repeat(title.len())).unwrap(),
+    }
+}
+
+/// Active nodes of a tree, each followed by its active children.
+pub(crate) fn active_chain(tree: &StructureTree) -> Vec<NodeId> {
+    let mut chain = Vec::new();
+    let mut stack: Vec<NodeId> = tree.roots.iter().flatten().rev().copied().collect();
+    while let Some(node_id) = stack.pop() {
+        if !tree.get_node(node_id).expect("Node should exist").active {
+            continue;
+        }
+
+        chain.push(node_id);
+        let children: Vec<NodeId> = node_id.children(&tree.arena).collect();
+        stack.extend(children.into_iter().rev());
+    }
+
+    chain
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use foxhole_production_calculator_types::Material;
+
+    use super::*;
+    use crate::test::{build_structures, setup_test_structure_maps};
+
+    fn report(format: ReportFormat) -> String {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::ConcreteMaterials, 2)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+
+        rg.report(&trees, &reqs, format)
+    }
+
+    #[test]
+    fn test_markdown_report() {
+        let report = report(ReportFormat::Markdown);
+
+        assert!(report.starts_with("## Buildings\n\n| Building | Upgrade | Count |\n"));
+        assert!(report.contains("| --- | --- | ---: |\n"));
+        assert!(report.contains("| upgrade_a | upgrade_a_1 | 1.00 |\n"));
+        assert!(report.contains("| Coal | 1.00 |\n"));
+        assert!(report.contains("## Power\n\n3.00 MW\n"));
+        assert!(report.contains("| Basic Materials | 4 |\n"));
+    }
+
+    #[test]
+    fn test_text_report_alignment() {
+        let report = report(ReportFormat::Text);
+
+        assert!(report.starts_with("Buildings\n=========\n"));
+        assert!(report.contains(
+            "Building   Upgrade      Count\n\
+             ---------  -----------  -----\n\
+             upgrade_a  upgrade_a_1   1.00\n\
+             upgrade_c  -             2.00\n"
+        ));
+        assert!(report.contains("Material  Rate (/h)\n--------  ---------\nCoal           1.00\n"));
+    }
+
+    #[test]
+    fn test_production_chain_after_switching() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::ConcreteMaterials, 1)].into_iter().collect();
+        let mut trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let chain = |trees: &[StructureTree]| {
+            let reqs = rg.factory_requirements_from_trees(trees, HashSet::new());
+            let report = rg.report(trees, &reqs, ReportFormat::Text);
+            report
+                .split("Production Chain\n================\n")
+                .nth(1)
+                .unwrap()
+                .to_string()
+        };
+
+        // Half a building is shown as such, not rounded up to whole buildings.
+        assert_eq!(
+            chain(&trees),
+            "1.00x upgrade_c -> Concrete Materials 1.00/h\n  \
+             0.50x upgrade_a (upgrade_a_1) -> Coke 1.00/h\n"
+        );
+
+        // The replaced alternative drops out of the chain entirely.
+        let root = trees[0].roots.as_ref().unwrap()[0];
+        let slower = root.children(&trees[0].arena).nth(1).unwrap();
+        trees[0].activate_node(slower);
+        assert_eq!(
+            chain(&trees),
+            "1.00x upgrade_c -> Concrete Materials 1.00/h\n  \
+             1.00x upgrade_a -> Coke 1.00/h\n"
+        );
+    }
+}
@@ -0,0 +1,203 @@
+//! Material flow data for Sankey diagrams.
+
+use std::collections::BTreeMap;
+
+use foxhole_production_calculator_types::Material;
+use serde::Serialize;
+
+use crate::{ResourceGraph, StructureTree};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SankeyNodeKind {
+    Material,
+    Building,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SankeyNode {
+    pub name: String,
+    pub kind: SankeyNodeKind,
+}
+
+/// A flow between two nodes, referenced by their index in [`SankeyDiagram::nodes`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SankeyLink {
+    pub source: usize,
+    pub target: usize,
+    /// [Unit/Hour]
+    pub value: f32,
+}
+
+/// Nodes and links in the shape most Sankey renderers, such as d3-sankey, take as input.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SankeyDiagram {
+    pub nodes: Vec<SankeyNode>,
+    pub links: Vec<SankeyLink>,
+}
+
+type NodeKey = (SankeyNodeKind, String);
+
+impl ResourceGraph {
+    /// Flows from materials into buildings and from buildings into materials, summed over the
+    /// active nodes of every tree. Buildings are grouped by structure and upgrade.
+    pub fn sankey(&self, trees: &[StructureTree]) -> SankeyDiagram {
+        let mut flows: BTreeMap<(NodeKey, NodeKey), f32> = BTreeMap::new();
+
+        for tree in trees {
+            let mut stack = tree.roots.clone().unwrap_or_default();
+            while let Some(node_id) = stack.pop() {
+                let node = tree.get_node(node_id).expect("Node should exist");
+                if !node.active {
+                    continue;
+                }
+                stack.extend(node_id.children(&tree.arena));
+
+                let details = self.node_details(tree, node_id).expect("Node should exist");
+                let building = match &details.upgrade {
+                    Some(upgrade) => format!("{} ({})", details.building, upgrade),
+                    None => details.building.clone(),
+                };
+                let building = (SankeyNodeKind::Building, building);
+
+                *flows
+                    .entry((building.clone(), material_node(details.output.material)))
+                    .or_default() += details.output.rate;
+                for input in details.inputs {
+                    *flows
+                        .entry((material_node(input.material), building.clone()))
+                        .or_default() += input.rate;
+                }
+            }
+        }
+
+        let mut indices: BTreeMap<NodeKey, usize> = BTreeMap::new();
+        for (source, target) in flows.keys() {
+            indices.entry(source.clone()).or_default();
+            indices.entry(target.clone()).or_default();
+        }
+
+        let mut nodes = Vec::new();
+        for ((kind, name), idx) in indices.iter_mut() {
+            *idx = nodes.len();
+            nodes.push(SankeyNode {
+                name: name.clone(),
+                kind: *kind,
+            });
+        }
+
+        let links = flows
+            .into_iter()
+            .map(|((source, target), value)| SankeyLink {
+                source: indices[&source],
+                target: indices[&target],
+                value,
+            })
+            .collect();
+
+        SankeyDiagram { nodes, links }
+    }
+}
+
+fn material_node(material: Material) -> NodeKey {
+    (SankeyNodeKind::Material, material.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::stockpile::Stockpile;
+    use crate::test::{build_structures, setup_test_structure_maps};
+
+    fn link_value(diagram: &SankeyDiagram, source: &str, target: &str) -> Option<f32> {
+        let index = |name: &str| diagram.nodes.iter().position(|node| node.name == name);
+        let (source, target) = (index(source)?, index(target)?);
+
+        diagram
+            .links
+            .iter()
+            .find(|link| link.source == source && link.target == target)
+            .map(|link| link.value)
+    }
+
+    #[test]
+    fn test_sankey_chain() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::ConcreteMaterials, 2)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let diagram = rg.sankey(&trees);
+
+        assert_eq!(diagram.nodes.len(), 5);
+        assert_eq!(diagram.links.len(), 4);
+        assert_eq!(
+            link_value(&diagram, "Coal", "upgrade_a (upgrade_a_1)"),
+            Some(1.0)
+        );
+        assert_eq!(
+            link_value(&diagram, "upgrade_c", "Concrete Materials"),
+            Some(2.0)
+        );
+        assert_eq!(link_value(&diagram, "Coal", "upgrade_a"), None);
+    }
+
+    #[test]
+    fn test_sankey_skips_inactive_and_stocked() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::ConcreteMaterials, 2), (Material::Coke, 10)]
+            .into_iter()
+            .collect();
+        let mut trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let slower = trees[0].roots.clone().unwrap()[1];
+        trees[0].activate_node(slower);
+        let diagram = rg.sankey(&trees);
+
+        // Only the Concrete tree still runs the upgrade, and the deactivated one adds nothing.
+        assert_eq!(
+            link_value(&diagram, "upgrade_a (upgrade_a_1)", "Coke"),
+            Some(2.0)
+        );
+        assert_eq!(link_value(&diagram, "upgrade_a", "Coke"), Some(10.0));
+        assert_eq!(link_value(&diagram, "Coal", "upgrade_a"), Some(10.0));
+
+        // A target taken entirely from stock has no flows at all.
+        let stockpile = Stockpile::new(vec![(Material::Coke, 10)].into_iter().collect(), 1.0);
+        let outputs = vec![(Material::Coke, 10)].into_iter().collect();
+        let trees =
+            rg.calculate_factory_requirements_with_stockpile(outputs, HashSet::new(), &stockpile);
+        assert_eq!(rg.sankey(&trees), SankeyDiagram::default());
+    }
+
+    #[test]
+    fn test_sankey_json() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::Coke, 2)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let json = serde_json::to_value(rg.sankey(&trees)).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "nodes": [
+                    {"name": "Coal", "kind": "material"},
+                    {"name": "Coke", "kind": "material"},
+                    {"name": "upgrade_a (upgrade_a_1)", "kind": "building"},
+                ],
+                "links": [
+                    {"source": 0, "target": 2, "value": 1.0},
+                    {"source": 2, "target": 1, "value": 2.0},
+                ],
+            })
+        );
+    }
+}
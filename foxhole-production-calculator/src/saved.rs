@@ -0,0 +1,297 @@
+//! Saving trees, along with the alternatives chosen in them, and loading them back.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use foxhole_production_calculator_types::{Material, Output};
+use indextree::NodeId;
+use serde::{Deserialize, Serialize};
+
+use crate::preferences::ChannelRef;
+use crate::{ResourceGraph, StructureTree};
+
+/// Version written by [`StructureTree::save`]. Bumped whenever the saved form changes.
+pub const SAVED_TREE_VERSION: u32 = 1;
+
+/// The alternative chosen for one step of a tree.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Selection {
+    /// Materials from the tree's target down to the one the choice produces.
+    pub path: Vec<Material>,
+    #[serde(flatten)]
+    pub choice: ChannelRef,
+}
+
+/// Stable form of a [`StructureTree`]. Only the target, user inputs and choices are kept, the
+/// tree itself is recalculated from the current structure data when loaded.
+///
+/// Stockpile state isn't kept either: a tree calculated against a [`crate::stockpile::Stockpile`]
+/// loads as if nothing were stockpiled, with every input made or brought in again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedTree {
+    pub version: u32,
+    pub target: Output,
+    pub user_inputs: BTreeSet<Material>,
+    pub selections: Vec<Selection>,
+}
+
+/// A saved tree loaded against the current structure data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadedTree {
+    pub tree: StructureTree,
+    /// Selections for steps or alternatives that no longer exist. These are left at the default
+    /// choice.
+    pub stale: Vec<Selection>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SavedTreeError {
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for SavedTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SavedTreeError::UnsupportedVersion(version) => write!(
+                f,
+                "Saved tree version {} isn't supported, expected {}",
+                version, SAVED_TREE_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SavedTreeError {}
+
+impl StructureTree {
+    /// Capture the tree's target, user inputs and the alternative chosen at every step with
+    /// more than one option. Returns `None` for trees that weren't calculated from a target.
+    pub fn save(&self) -> Option<SavedTree> {
+        let target = self.target.clone()?;
+        let mut selections = Vec::new();
+
+        let mut stack: Vec<(NodeId, Vec<Material>)> = self
+            .roots
+            .iter()
+            .flatten()
+            .map(|root| (*root, vec![target.material]))
+            .collect();
+        while let Some((node_id, path)) = stack.pop() {
+            let node = self.get_node(node_id).expect("Node should exist");
+            if !node.active {
+                continue;
+            }
+
+            if self.options(node).len() > 1 {
+                selections.push(Selection {
+                    path: path.clone(),
+                    choice: ChannelRef::from_key(&node.structure),
+                });
+            }
+
+            for child in node_id.children(&self.arena) {
+                let material = self
+                    .get_node(child)
+                    .expect("Node should exist")
+                    .structure
+                    .output
+                    .material;
+                let mut child_path = path.clone();
+                child_path.push(material);
+                stack.push((child, child_path));
+            }
+        }
+        selections.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Some(SavedTree {
+            version: SAVED_TREE_VERSION,
+            target,
+            user_inputs: self.user_inputs.iter().copied().collect(),
+            selections,
+        })
+    }
+
+    /// Activate the chosen alternatives, from the target down so a choice higher up decides
+    /// which steps exist below it. Returns the selections that don't match anything in the tree.
+    pub fn apply_selections(&mut self, selections: &[Selection]) -> Vec<Selection> {
+        let mut selections = selections.to_vec();
+        selections.sort_by_key(|selection| selection.path.len());
+
+        let mut stale = Vec::new();
+        for selection in selections {
+            let chosen = self.find_options(&selection.path).and_then(|options| {
+                options.into_iter().find(|node_id| {
+                    let node = self.get_node(*node_id).expect("Node should exist");
+                    selection.choice.matches(&node.structure)
+                })
+            });
+
+            match chosen {
+                Some(node_id) => self.activate_node(node_id),
+                None => stale.push(selection),
+            }
+        }
+
+        stale
+    }
+
+    /// The alternatives producing the last material in `path`, following active nodes down
+    /// from the roots.
+    fn find_options(&self, path: &[Material]) -> Option<Vec<NodeId>> {
+        let (first, rest) = path.split_first()?;
+        if self.target.as_ref()?.material != *first {
+            return None;
+        }
+
+        let mut options = self.roots.clone()?;
+        for material in rest {
+            let active = options
+                .into_iter()
+                .find(|node_id| self.get_node(*node_id).expect("Node should exist").active)?;
+            options = active
+                .children(&self.arena)
+                .filter(|child| {
+                    let node = self.get_node(*child).expect("Node should exist");
+                    node.structure.output.material == *material
+                })
+                .collect();
+        }
+
+        (!options.is_empty()).then_some(options)
+    }
+}
+
+impl ResourceGraph {
+    /// Recalculate a saved tree and reapply its choices. The tree doesn't draw on any
+    /// stockpile, see [`SavedTree`].
+    pub fn load_tree(&self, saved: &SavedTree) -> Result<LoadedTree, SavedTreeError> {
+        if saved.version != SAVED_TREE_VERSION {
+            return Err(SavedTreeError::UnsupportedVersion(saved.version));
+        }
+
+        let outputs = vec![(saved.target.material, saved.target.value)]
+            .into_iter()
+            .collect();
+        let mut trees = self
+            .calculate_factory_requirements(outputs, saved.user_inputs.iter().copied().collect());
+        let mut tree = trees.pop().expect("A tree is calculated for every target");
+        let stale = tree.apply_selections(&saved.selections);
+
+        Ok(LoadedTree { tree, stale })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::preferences::UpgradeRef;
+    use crate::stockpile::Stockpile;
+    use crate::test::{build_structures, setup_test_structure_maps};
+
+    #[test]
+    fn test_save_and_load_round_trip() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::ConcreteMaterials, 2)].into_iter().collect();
+        let mut trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let slower = trees[0].find_options(&[Material::ConcreteMaterials, Material::Coke]);
+        trees[0].activate_node(slower.unwrap()[1]);
+
+        let saved = trees[0].save().unwrap();
+        let json = serde_json::to_string(&saved).unwrap();
+        let loaded = rg
+            .load_tree(&serde_json::from_str::<SavedTree>(&json).unwrap())
+            .unwrap();
+
+        assert!(loaded.stale.is_empty());
+        assert_eq!(
+            rg.factory_requirements_from_trees(&[loaded.tree], HashSet::new()),
+            rg.factory_requirements_from_trees(&trees, HashSet::new())
+        );
+        assert_eq!(
+            saved.selections,
+            vec![Selection {
+                path: vec![Material::ConcreteMaterials, Material::Coke],
+                choice: ChannelRef::new(UpgradeRef::new("upgrade_a", None), 0),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_load_reports_stale_selections() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let missing_upgrade = Selection {
+            path: vec![Material::Coke],
+            choice: ChannelRef::new(UpgradeRef::new("upgrade_a", Some("removed")), 0),
+        };
+        let missing_step = Selection {
+            path: vec![Material::Coke, Material::Coal],
+            choice: ChannelRef::new(UpgradeRef::new("upgrade_a", None), 0),
+        };
+        let saved = SavedTree {
+            version: SAVED_TREE_VERSION,
+            target: Output::new(Material::Coke, 10),
+            user_inputs: BTreeSet::new(),
+            selections: vec![missing_step.clone(), missing_upgrade.clone()],
+        };
+        let loaded = rg.load_tree(&saved).unwrap();
+
+        assert_eq!(loaded.stale, vec![missing_upgrade, missing_step]);
+        let reqs = rg.factory_requirements_from_trees(&[loaded.tree], HashSet::new());
+        assert_eq!(reqs.buildings[0].upgrade, Some("upgrade_a_1".to_string()));
+    }
+
+    #[test]
+    fn test_load_unsupported_version() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let saved = SavedTree {
+            version: SAVED_TREE_VERSION + 1,
+            target: Output::new(Material::Coke, 10),
+            user_inputs: BTreeSet::new(),
+            selections: Vec::new(),
+        };
+
+        assert_eq!(
+            rg.load_tree(&saved).unwrap_err(),
+            SavedTreeError::UnsupportedVersion(SAVED_TREE_VERSION + 1)
+        );
+    }
+
+    #[test]
+    fn test_load_drops_stockpile() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let stockpile = Stockpile::new(vec![(Material::Coal, 5)].into_iter().collect(), 1.0);
+        let outputs = vec![(Material::Coke, 10)].into_iter().collect();
+        let trees =
+            rg.calculate_factory_requirements_with_stockpile(outputs, HashSet::new(), &stockpile);
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+        assert_eq!(reqs.stockpile_usage.len(), 1);
+
+        let loaded = rg.load_tree(&trees[0].save().unwrap()).unwrap();
+        let reloaded = rg.factory_requirements_from_trees(&[loaded.tree], HashSet::new());
+        assert!(reloaded.stockpile_usage.is_empty());
+        assert_eq!(
+            reloaded,
+            rg.factory_requirements_from_trees(
+                &rg.calculate_factory_requirements(
+                    vec![(Material::Coke, 10)].into_iter().collect(),
+                    HashSet::new()
+                ),
+                HashSet::new()
+            )
+        );
+    }
+}
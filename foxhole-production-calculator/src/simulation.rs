@@ -0,0 +1,493 @@
+//! Second by second simulation of a planned factory.
+//!
+//! Every station runs its production channel in cycles of `rate` seconds. Stations pull inputs
+//! from shared storage into their own input buffers, and push finished outputs back into
+//! storage. Unlike the steady state numbers from [`ResourceGraph::factory_requirements_from_trees`]
+//! this shows startup delays, starved buildings and buildings blocked by full storage.
+
+use std::collections::{HashMap, HashSet};
+
+use foxhole_production_calculator_types::{Material, ProductionChannel};
+use serde::Serialize;
+
+use crate::{FactoryRequirements, ResourceGraph, StructureKey, StructureTree};
+
+/// How materials from outside the factory arrive in storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExternalSupply {
+    /// Outside inputs are always available.
+    Unlimited,
+    /// Outside inputs arrive steadily at the hourly rate the plan calls for.
+    Planned,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationConfig {
+    /// Length of the run in seconds.
+    pub duration: u64,
+    /// Seconds between inventory samples.
+    pub sample_interval: u64,
+    /// Production cycles worth of inputs each station can buffer.
+    pub buffer_cycles: u32,
+    /// Most of a single material that can sit in storage between stations. Finished products
+    /// that nothing in the factory consumes are never limited.
+    pub storage_limit: Option<f32>,
+    pub external_supply: ExternalSupply,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            duration: 6 * 60 * 60,
+            sample_interval: 10 * 60,
+            buffer_cycles: 2,
+            storage_limit: None,
+            external_supply: ExternalSupply::Unlimited,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StationReport {
+    pub structure: String,
+    pub output: Material,
+    pub count: f32,
+    /// Production cycles completed.
+    pub cycles: u64,
+    /// Seconds spent waiting on inputs.
+    pub starved: u64,
+    /// Seconds spent waiting for room in storage to drop off outputs.
+    pub blocked: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InventorySample {
+    /// Seconds since the start of the run.
+    pub time: u64,
+    pub levels: HashMap<Material, f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SimulationReport {
+    /// Length of the run in seconds.
+    pub duration: u64,
+    /// Actual production over the run, per material. [Unit/Hour]
+    pub throughput: HashMap<Material, f32>,
+    pub stations: Vec<StationReport>,
+    pub inventory: Vec<InventorySample>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StationState {
+    Idle,
+    Running(u64),
+    Finished,
+}
+
+struct Station {
+    structure: String,
+    output: Material,
+    count: f32,
+    channel: ProductionChannel,
+    buffer: HashMap<Material, f32>,
+    state: StationState,
+    cycles: u64,
+    starved: u64,
+    blocked: u64,
+}
+
+impl Station {
+    fn new(structure: String, output: Material, count: f32, channel: ProductionChannel) -> Self {
+        Self {
+            structure,
+            output,
+            count,
+            channel,
+            buffer: HashMap::new(),
+            state: StationState::Idle,
+            cycles: 0,
+            starved: 0,
+            blocked: 0,
+        }
+    }
+
+    fn inputs_per_cycle(&self) -> impl Iterator<Item = (Material, f32)> + '_ {
+        self.channel
+            .inputs
+            .iter()
+            .map(|input| (input.material, input.value as f32 * self.count))
+    }
+
+    fn outputs_per_cycle(&self) -> impl Iterator<Item = (Material, f32)> + '_ {
+        self.channel
+            .outputs
+            .iter()
+            .map(|output| (output.material, output.value as f32 * self.count))
+    }
+}
+
+struct Simulation<'c> {
+    config: &'c SimulationConfig,
+    stations: Vec<Station>,
+    storage: HashMap<Material, f32>,
+    external: HashMap<Material, f32>,
+    sinks: HashSet<Material>,
+    produced: HashMap<Material, f32>,
+}
+
+impl<'c> Simulation<'c> {
+    fn new(
+        config: &'c SimulationConfig,
+        mut stations: Vec<Station>,
+        planned_inputs: &HashMap<Material, f32>,
+    ) -> Self {
+        stations.sort_by(|a, b| a.structure.cmp(&b.structure).then(a.output.cmp(&b.output)));
+
+        let consumed: HashSet<Material> = stations
+            .iter()
+            .flat_map(|station| station.channel.inputs.iter().map(|input| input.material))
+            .collect();
+        let produced: HashSet<Material> = stations
+            .iter()
+            .flat_map(|station| station.channel.outputs.iter().map(|output| output.material))
+            .collect();
+
+        let external = consumed
+            .difference(&produced)
+            .map(|material| {
+                let rate = planned_inputs.get(material).copied().unwrap_or_default();
+                (*material, rate)
+            })
+            .collect();
+        let sinks = produced.difference(&consumed).copied().collect();
+
+        Self {
+            config,
+            stations,
+            storage: HashMap::new(),
+            external,
+            sinks,
+            produced: HashMap::new(),
+        }
+    }
+
+    fn run(mut self) -> SimulationReport {
+        let mut inventory = Vec::new();
+        for time in 0..self.config.duration {
+            if self.config.sample_interval > 0 && time % self.config.sample_interval == 0 {
+                inventory.push(self.sample(time));
+            }
+
+            self.tick();
+        }
+        inventory.push(self.sample(self.config.duration));
+
+        let hours = self.config.duration as f32 / (60.0 * 60.0);
+        let throughput = self
+            .produced
+            .iter()
+            .map(|(material, amount)| (*material, amount / hours))
+            .collect();
+
+        let stations = self
+            .stations
+            .into_iter()
+            .map(|station| StationReport {
+                structure: station.structure,
+                output: station.output,
+                count: station.count,
+                cycles: station.cycles,
+                starved: station.starved,
+                blocked: station.blocked,
+            })
+            .collect();
+
+        SimulationReport {
+            duration: self.config.duration,
+            throughput,
+            stations,
+            inventory,
+        }
+    }
+
+    fn sample(&self, time: u64) -> InventorySample {
+        InventorySample {
+            time,
+            levels: self.storage.clone(),
+        }
+    }
+
+    fn has_room(&self, material: Material, amount: f32) -> bool {
+        match self.config.storage_limit {
+            Some(limit) if !self.sinks.contains(&material) => {
+                self.storage.get(&material).copied().unwrap_or_default() + amount <= limit
+            }
+            _ => true,
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.config.external_supply == ExternalSupply::Planned {
+            for (material, hourly_rate) in &self.external {
+                let stored = self.storage.entry(*material).or_default();
+                *stored += hourly_rate / (60.0 * 60.0);
+                if let Some(limit) = self.config.storage_limit {
+                    *stored = stored.min(limit);
+                }
+            }
+        }
+
+        for idx in 0..self.stations.len() {
+            self.refill(idx);
+
+            let station = &mut self.stations[idx];
+            if station.state == StationState::Idle {
+                let ready = station
+                    .inputs_per_cycle()
+                    .all(|(material, amount)| station.buffer[&material] >= amount);
+
+                if ready {
+                    for (material, amount) in station.inputs_per_cycle().collect::<Vec<_>>() {
+                        *station.buffer.entry(material).or_default() -= amount;
+                    }
+                    station.state = StationState::Running(station.channel.rate);
+                } else {
+                    station.starved += 1;
+                }
+            }
+
+            if let StationState::Running(remaining) = station.state {
+                station.state = if remaining <= 1 {
+                    StationState::Finished
+                } else {
+                    StationState::Running(remaining - 1)
+                };
+            }
+
+            if station.state == StationState::Finished {
+                let outputs: Vec<_> = station.outputs_per_cycle().collect();
+                if outputs
+                    .iter()
+                    .all(|(material, amount)| self.has_room(*material, *amount))
+                {
+                    for (material, amount) in outputs {
+                        *self.storage.entry(material).or_default() += amount;
+                        *self.produced.entry(material).or_default() += amount;
+                    }
+
+                    let station = &mut self.stations[idx];
+                    station.state = StationState::Idle;
+                    station.cycles += 1;
+                } else {
+                    self.stations[idx].blocked += 1;
+                }
+            }
+        }
+    }
+
+    /// Top up a station's input buffers from storage.
+    fn refill(&mut self, idx: usize) {
+        let station = &mut self.stations[idx];
+        let buffer_cycles = self.config.buffer_cycles.max(1) as f32;
+
+        for (material, amount) in station.inputs_per_cycle().collect::<Vec<_>>() {
+            let buffered = station.buffer.entry(material).or_default();
+            let wanted = (amount * buffer_cycles - *buffered).max(0.0);
+
+            let taken = if self.config.external_supply == ExternalSupply::Unlimited
+                && self.external.contains_key(&material)
+            {
+                wanted
+            } else {
+                let stored = self.storage.entry(material).or_default();
+                let taken = wanted.min(*stored);
+                *stored -= taken;
+
+                taken
+            };
+
+            *buffered += taken;
+        }
+    }
+}
+
+impl ResourceGraph {
+    /// Simulate the active nodes of the given trees.
+    ///
+    /// Nodes using the same structure and production channel are combined into one station.
+    pub fn simulate_trees(
+        &self,
+        trees: &[StructureTree],
+        user_inputs: HashSet<Material>,
+        config: &SimulationConfig,
+    ) -> SimulationReport {
+        let mut counts: HashMap<StructureKey, f32> = HashMap::new();
+        for tree in trees {
+            for node in tree.traverse() {
+                *counts.entry(node.structure.clone()).or_default() += node.count;
+            }
+        }
+
+        let stations = counts
+            .into_iter()
+            .map(|(key, count)| {
+                Station::new(
+                    key.structure_name(),
+                    key.output.material,
+                    count,
+                    self.production_channel(&key).clone(),
+                )
+            })
+            .collect();
+
+        let reqs = self.factory_requirements_from_trees(trees, user_inputs);
+
+        Simulation::new(config, stations, &reqs.inputs).run()
+    }
+
+    /// Simulate the buildings in a set of factory requirements, each group running the channel
+    /// the requirements recorded for it.
+    pub fn simulate_requirements(
+        &self,
+        reqs: &FactoryRequirements,
+        config: &SimulationConfig,
+    ) -> SimulationReport {
+        let stations = reqs
+            .channels
+            .iter()
+            .map(|channel| {
+                let structure = self
+                    .catalog
+                    .structure_map
+                    .get(&channel.building)
+                    .expect("Structure should exist");
+                let upgrade = match &channel.upgrade {
+                    Some(upgrade) => structure
+                        .upgrades
+                        .get(upgrade)
+                        .expect("Upgrade should exist"),
+                    None => &structure.default_upgrade,
+                };
+                let production_channel = &upgrade.production_channels[channel.channel];
+                let name = match &channel.upgrade {
+                    Some(upgrade) => format!("{}-{}", channel.building, upgrade),
+                    None => channel.building.clone(),
+                };
+
+                Station::new(
+                    name,
+                    production_channel.outputs[0].material,
+                    channel.count,
+                    production_channel.clone(),
+                )
+            })
+            .collect();
+
+        Simulation::new(config, stations, &reqs.inputs).run()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{build_structures, setup_test_structure_maps};
+
+    #[test]
+    fn test_simulate_steady_output() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::Coke, 10)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let config = SimulationConfig {
+            duration: 10 * 60 * 60,
+            ..Default::default()
+        };
+        let report = rg.simulate_trees(&trees, HashSet::new(), &config);
+
+        assert_eq!(report.throughput[&Material::Coke], 10.0);
+        assert_eq!(report.stations.len(), 1);
+        assert_eq!(report.stations[0].cycles, 10);
+        assert_eq!(report.stations[0].starved, 0);
+        assert_eq!(report.inventory.len(), 61);
+    }
+
+    #[test]
+    fn test_simulate_startup_starvation() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::ConcreteMaterials, 1)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let config = SimulationConfig {
+            duration: 4 * 60 * 60,
+            external_supply: ExternalSupply::Planned,
+            ..Default::default()
+        };
+        let report = rg.simulate_trees(&trees, HashSet::new(), &config);
+
+        let concrete = report
+            .stations
+            .iter()
+            .find(|station| station.output == Material::ConcreteMaterials)
+            .unwrap();
+        // Coke only shows up after the first refinery cycle finishes.
+        assert!(concrete.starved >= 60 * 60);
+        assert!(report.throughput[&Material::ConcreteMaterials] < 1.0);
+    }
+
+    #[test]
+    fn test_simulate_storage_limit_blocks() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let outputs = vec![(Material::ConcreteMaterials, 1)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+        let config = SimulationConfig {
+            duration: 4 * 60 * 60,
+            storage_limit: Some(0.5),
+            ..Default::default()
+        };
+        let report = rg.simulate_requirements(&reqs, &config);
+
+        let coke = report
+            .stations
+            .iter()
+            .find(|station| station.output == Material::Coke)
+            .unwrap();
+        assert!(coke.blocked > 0);
+        assert_eq!(coke.cycles, 0);
+        assert!(!report.throughput.contains_key(&Material::ConcreteMaterials));
+    }
+
+    #[test]
+    fn test_simulate_requirements_channels() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        // Both rockets come out of the same building, running a different channel for each.
+        let outputs = vec![
+            (Material::Rocket4CFire, 1),
+            (Material::Rocket3CHighExplosive, 2),
+        ]
+        .into_iter()
+        .collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+        let config = SimulationConfig {
+            duration: 10 * 60 * 60,
+            ..Default::default()
+        };
+        let report = rg.simulate_requirements(&reqs, &config);
+
+        assert_eq!(reqs.buildings.len(), 1);
+        assert_eq!(report.stations.len(), 2);
+        assert_eq!(report.throughput[&Material::Rocket4CFire], 1.0);
+        assert_eq!(report.throughput[&Material::Rocket3CHighExplosive], 2.0);
+    }
+}
@@ -0,0 +1,399 @@
+//! Planning against materials already sitting in storage.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use foxhole_production_calculator_types::Material;
+use indextree::NodeId;
+use serde::{Deserialize, Serialize};
+
+/// Materials on hand, to be used up over a planning horizon.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stockpile {
+    pub inventory: HashMap<Material, u64>,
+    /// Hours the stockpile has to last.
+    pub horizon: f32,
+}
+
+impl Stockpile {
+    pub fn new(inventory: HashMap<Material, u64>, horizon: f32) -> Self {
+        Self { inventory, horizon }
+    }
+}
+
+/// Part of a node's input covered by the stockpile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StockpileDraw {
+    /// Node consuming the material, or `None` when the tree's target is taken from stock.
+    pub consumer: Option<NodeId>,
+    pub material: Material,
+    /// [Unit/Hour]
+    pub rate: f32,
+    /// Total taken over the stockpile's horizon, or an hour's worth for a capped input.
+    pub amount: f32,
+    /// Brought in from outside the factory under a cap rather than taken from the stockpile.
+    /// These are reported as inputs.
+    pub capped_input: bool,
+}
+
+/// Stockpile use by a single branch of the plan.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StockpileUsage {
+    pub material: Material,
+    /// Structure consuming the material, or `None` for a target taken straight from stock.
+    pub consumer: Option<String>,
+    /// [Unit/Hour]
+    pub rate: f32,
+    /// Total taken over the stockpile's horizon.
+    pub amount: f32,
+}
+
+/// Hourly supply left in a stockpile, and under the caps of capped inputs, while a plan is being
+/// built.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct StockpileSupply {
+    remaining: HashMap<Material, f32>,
+    horizon: f32,
+    caps: HashMap<Material, f32>,
+}
+
+impl StockpileSupply {
+    pub(crate) fn new(stockpile: &Stockpile) -> Self {
+        let remaining = if stockpile.horizon > 0.0 {
+            stockpile
+                .inventory
+                .iter()
+                .map(|(material, amount)| (*material, *amount as f32 / stockpile.horizon))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+
+        Self {
+            remaining,
+            horizon: stockpile.horizon,
+            caps: HashMap::new(),
+        }
+    }
+
+    /// Also bring materials in from outside the factory, up to an hourly cap each.
+    pub(crate) fn with_capped_inputs(mut self, capped_inputs: &HashMap<Material, u64>) -> Self {
+        self.caps = capped_inputs
+            .iter()
+            .map(|(material, cap)| (*material, *cap as f32))
+            .collect();
+
+        self
+    }
+
+    /// Cover as much of `rate` as possible, from the stockpile first and then from capped
+    /// inputs.
+    pub(crate) fn draw(
+        &mut self,
+        material: Material,
+        rate: f32,
+        consumer: Option<NodeId>,
+    ) -> Vec<StockpileDraw> {
+        let mut draws = Vec::new();
+        let mut rate = rate;
+        for capped_input in [false, true] {
+            let pool = if capped_input {
+                &mut self.caps
+            } else {
+                &mut self.remaining
+            };
+            let remaining = match pool.get_mut(&material) {
+                Some(remaining) => remaining,
+                None => continue,
+            };
+            let drawn = remaining.min(rate);
+            if drawn <= 0.0 {
+                continue;
+            }
+
+            *remaining -= drawn;
+            rate -= drawn;
+
+            let amount = if capped_input {
+                drawn
+            } else {
+                drawn * self.horizon
+            };
+            draws.push(StockpileDraw {
+                consumer,
+                material,
+                rate: drawn,
+                amount,
+                capped_input,
+            });
+        }
+
+        draws
+    }
+
+    /// Whether any of a material is left, in the stockpile or under a cap.
+    pub(crate) fn has_stock(&self, material: Material) -> bool {
+        [&self.remaining, &self.caps].iter().any(|pool| {
+            pool.get(&material)
+                .is_some_and(|remaining| *remaining > 0.0)
+        })
+    }
+
+    /// Give back what an earlier draw took.
+    pub(crate) fn release(&mut self, draw: &StockpileDraw) {
+        let pool = if draw.capped_input {
+            &mut self.caps
+        } else {
+            &mut self.remaining
+        };
+        *pool.entry(draw.material).or_default() += draw.rate;
+    }
+}
+
+/// One [`StockpileSupply`] shared by every tree planned together, so no two trees can draw the
+/// same inventory.
+#[derive(Debug, Default)]
+pub(crate) struct SharedSupply(Arc<Mutex<StockpileSupply>>);
+
+impl SharedSupply {
+    pub(crate) fn new(supply: StockpileSupply) -> Self {
+        Self(Arc::new(Mutex::new(supply)))
+    }
+
+    /// Another handle on the same supply.
+    pub(crate) fn share(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+
+    /// A separate supply starting with what's left in this one.
+    pub(crate) fn detach(&self) -> Self {
+        Self::new(self.lock().clone())
+    }
+
+    pub(crate) fn is_shared_with(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, StockpileSupply> {
+        self.0
+            .lock()
+            .expect("Stockpile supply should not be poisoned")
+    }
+}
+
+impl PartialEq for SharedSupply {
+    fn eq(&self, other: &Self) -> bool {
+        self.is_shared_with(other) || *self.lock() == *other.lock()
+    }
+}
+
+/// Add a draw to the stockpile usage, or to the external inputs for a capped input.
+pub(crate) fn add_draw(
+    usage: &mut HashMap<(Material, Option<String>), (f32, f32)>,
+    inputs: &mut HashMap<Material, f32>,
+    draw: &StockpileDraw,
+    consumer: Option<String>,
+) {
+    if draw.capped_input {
+        *inputs.entry(draw.material).or_default() += draw.rate;
+        return;
+    }
+
+    let entry = usage.entry((draw.material, consumer)).or_default();
+    entry.0 += draw.rate;
+    entry.1 += draw.amount;
+}
+
+pub(crate) fn sorted_usage(
+    usage: HashMap<(Material, Option<String>), (f32, f32)>,
+) -> Vec<StockpileUsage> {
+    let mut usage: Vec<StockpileUsage> = usage
+        .into_iter()
+        .map(|((material, consumer), (rate, amount))| StockpileUsage {
+            material,
+            consumer,
+            rate,
+            amount,
+        })
+        .collect();
+    usage.sort_by(|a, b| (a.material, &a.consumer).cmp(&(b.material, &b.consumer)));
+
+    usage
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::test::{build_structures, setup_test_structure_maps};
+    use crate::{ResourceGraph, StructureTree};
+
+    #[test]
+    fn test_stockpile_covers_target() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let stockpile = Stockpile::new(vec![(Material::Coke, 20)].into_iter().collect(), 4.0);
+        let outputs = vec![(Material::Coke, 10)].into_iter().collect();
+        let trees =
+            rg.calculate_factory_requirements_with_stockpile(outputs, HashSet::new(), &stockpile);
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+
+        assert_eq!(reqs.buildings[0].count, 2.5);
+        assert_eq!(reqs.inputs[&Material::Coal], 2.5);
+        assert_eq!(
+            reqs.stockpile_usage,
+            vec![StockpileUsage {
+                material: Material::Coke,
+                consumer: None,
+                rate: 5.0,
+                amount: 20.0,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_stockpile_covers_branch_input() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let stockpile = Stockpile::new(vec![(Material::Coal, 1)].into_iter().collect(), 4.0);
+        let outputs = vec![(Material::ConcreteMaterials, 1)].into_iter().collect();
+        let trees =
+            rg.calculate_factory_requirements_with_stockpile(outputs, HashSet::new(), &stockpile);
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+
+        assert_eq!(reqs.inputs[&Material::Coal], 0.25);
+        assert_eq!(
+            reqs.stockpile_usage,
+            vec![StockpileUsage {
+                material: Material::Coal,
+                consumer: Some("upgrade_a-upgrade_a_1".to_string()),
+                rate: 0.25,
+                amount: 1.0,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_stockpile_fully_covers_target() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let stockpile = Stockpile::new(vec![(Material::Coke, 100)].into_iter().collect(), 2.0);
+        let outputs = vec![(Material::Coke, 10), (Material::ConcreteMaterials, 1)]
+            .into_iter()
+            .collect();
+        let trees =
+            rg.calculate_factory_requirements_with_stockpile(outputs, HashSet::new(), &stockpile);
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+
+        // All Coke, including the Concrete input, comes from stock.
+        assert_eq!(reqs.buildings.len(), 1);
+        assert_eq!(reqs.buildings[0].building, "upgrade_c");
+        assert!(!reqs.inputs.contains_key(&Material::Coal));
+    }
+
+    #[test]
+    fn test_toggling_alternatives_keeps_stock() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let stockpile = Stockpile::new(vec![(Material::Coal, 6)].into_iter().collect(), 1.0);
+        let outputs = vec![(Material::Coke, 10)].into_iter().collect();
+        let mut trees =
+            rg.calculate_factory_requirements_with_stockpile(outputs, HashSet::new(), &stockpile);
+        let roots = trees[0].roots.clone().unwrap();
+        let faster = rg.factory_requirements_from_trees(&trees, HashSet::new());
+        assert_eq!(faster.stockpile_usage[0].rate, 5.0);
+        assert!(faster.inputs.is_empty());
+
+        for _ in 0..2 {
+            trees[0].activate_node(roots[1]);
+            let slower = rg.factory_requirements_from_trees(&trees, HashSet::new());
+            assert_eq!(
+                slower.stockpile_usage,
+                vec![StockpileUsage {
+                    material: Material::Coal,
+                    consumer: Some("upgrade_a".to_string()),
+                    rate: 6.0,
+                    amount: 6.0,
+                }]
+            );
+            assert_eq!(slower.inputs[&Material::Coal], 4.0);
+
+            trees[0].activate_node(roots[0]);
+            assert_eq!(
+                rg.factory_requirements_from_trees(&trees, HashSet::new()),
+                faster
+            );
+        }
+    }
+
+    #[test]
+    fn test_trees_share_stock() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let stockpile = Stockpile::new(vec![(Material::Coal, 6)].into_iter().collect(), 1.0);
+        let outputs = vec![(Material::Coke, 10), (Material::ConcreteMaterials, 2)]
+            .into_iter()
+            .collect();
+        let mut trees =
+            rg.calculate_factory_requirements_with_stockpile(outputs, HashSet::new(), &stockpile);
+
+        // The Coke tree takes 5 of the 6 Coal, and the Concrete tree the last one. Switching the
+        // Coke tree to the slower option can only take back its own 5.
+        let slower = trees[0].roots.clone().unwrap()[1];
+        trees[0].activate_node(slower);
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+
+        let drawn: f32 = reqs.stockpile_usage.iter().map(|usage| usage.rate).sum();
+        assert_eq!(drawn, 6.0);
+        assert_eq!(reqs.inputs[&Material::Coal], 5.0);
+    }
+
+    #[test]
+    fn test_clones_keep_their_own_stock() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        // The Coke tree takes 5 of the 8 Coal and the Concrete tree 1, leaving 2.
+        let stockpile = Stockpile::new(vec![(Material::Coal, 8)].into_iter().collect(), 1.0);
+        let outputs = vec![(Material::Coke, 10), (Material::ConcreteMaterials, 2)]
+            .into_iter()
+            .collect();
+        let mut trees =
+            rg.calculate_factory_requirements_with_stockpile(outputs, HashSet::new(), &stockpile);
+        let slower = trees[0].roots.clone().unwrap()[1];
+
+        // Switching a clone uses up the clone's copy of what's left, not the original's.
+        let mut clone = trees[0].clone();
+        clone.activate_node(slower);
+        assert!(!trees[0].supply.is_shared_with(&clone.supply));
+
+        // Clones made together still share with each other.
+        let mut clones = StructureTree::clone_all(&trees);
+        assert!(clones[0].supply.is_shared_with(&clones[1].supply));
+        assert!(!clones[0].supply.is_shared_with(&trees[0].supply));
+        clones[0].activate_node(slower);
+
+        // The original gets its own 5 back plus the 2 left over.
+        trees[0].activate_node(slower);
+        let reqs = rg.factory_requirements_from_trees(&trees, HashSet::new());
+        let drawn: f32 = reqs.stockpile_usage.iter().map(|usage| usage.rate).sum();
+        assert_eq!(drawn, 8.0);
+        assert_eq!(reqs.inputs[&Material::Coal], 3.0);
+        assert_eq!(
+            rg.factory_requirements_from_trees(&clones, HashSet::new()),
+            reqs
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! Memoized subtrees. The tree below a material only depends on the material, the user inputs
+//! and the preferences, so it's worked out once at one unit an hour and scaled to the rate needed
+//! wherever the material shows up again.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use foxhole_production_calculator_types::{Material, Output};
+use indextree::NodeId;
+
+use crate::stockpile::StockpileSupply;
+use crate::{ResourceGraph, StructureKey, StructureTree, StructureTreeNode};
+
+/// Material produced and the user inputs the subtree was worked out with, in material order.
+pub(crate) type SubtreeKey = (Material, Vec<Material>);
+
+/// Subtrees worked out so far. Shared by clones of a [`ResourceGraph`], and started afresh when
+/// its preferences change.
+pub(crate) type SubtreeCache = Arc<Mutex<HashMap<SubtreeKey, Arc<Subtree>>>>;
+
+/// One node of a [`Subtree`], in the order the nodes were created.
+#[derive(Debug)]
+struct SubtreeNode {
+    structure: StructureKey,
+    /// Index of the parent node, `None` for the alternatives producing the material itself.
+    parent: Option<usize>,
+    /// Rate of this node's material needed by each of its parent's buildings.
+    input_rate: f32,
+    group: usize,
+    active: bool,
+    expanded: bool,
+}
+
+/// The nodes planned for a material, without a rate.
+#[derive(Debug)]
+pub(crate) struct Subtree {
+    nodes: Vec<SubtreeNode>,
+    groups: usize,
+    /// Every material the subtree asks for, including ones brought in from outside.
+    materials: HashSet<Material>,
+}
+
+impl Subtree {
+    /// Record the nodes of a tree planned for one material.
+    fn from_tree(graph: &ResourceGraph, tree: &StructureTree, material: Material) -> Self {
+        let mut indices: HashMap<NodeId, usize> = HashMap::new();
+        let mut nodes = Vec::new();
+        let mut materials = HashSet::from([material]);
+
+        for arena_node in tree.arena.iter() {
+            let node_id = tree
+                .arena
+                .get_node_id(arena_node)
+                .expect("Node should exist");
+            let node = arena_node.get();
+            indices.insert(node_id, nodes.len());
+
+            if node.active {
+                let production_channel = graph.production_channel(&node.structure);
+                materials.extend(production_channel.inputs.iter().map(|input| input.material));
+            }
+
+            let parent = arena_node.parent();
+            let input_rate = match parent {
+                Some(parent) => {
+                    let parent = tree.get_node(parent).expect("Node should exist");
+                    let production_channel = graph.production_channel(&parent.structure);
+                    let input = production_channel
+                        .inputs
+                        .iter()
+                        .find(|input| input.material == node.structure.output.material)
+                        .expect("Parent should consume its child's output");
+
+                    production_channel.hourly_rate(input.value)
+                }
+                None => 1.0,
+            };
+
+            nodes.push(SubtreeNode {
+                structure: node.structure.clone(),
+                parent: parent.map(|parent| indices[&parent]),
+                input_rate,
+                group: node.group,
+                active: node.active,
+                expanded: node.expanded,
+            });
+        }
+
+        Self {
+            nodes,
+            groups: tree.groups.len(),
+            materials,
+        }
+    }
+
+    /// Whether the stockpile could cover part of the subtree, which would change its shape.
+    pub(crate) fn draws_on(&self, supply: &StockpileSupply) -> bool {
+        self.materials
+            .iter()
+            .any(|material| supply.has_stock(*material))
+    }
+
+    /// Add the subtree to a tree, needed at `rate` by `parent_node`. Counts are worked out the
+    /// same way as when the tree is traversed, so the result matches a fresh calculation.
+    pub(crate) fn instantiate(
+        &self,
+        graph: &ResourceGraph,
+        rate: f32,
+        tree: &mut StructureTree,
+        parent_node: Option<NodeId>,
+    ) {
+        let group_offset = tree.groups.len();
+        tree.groups
+            .extend(std::iter::repeat_with(Vec::new).take(self.groups));
+
+        let mut node_ids: Vec<NodeId> = Vec::with_capacity(self.nodes.len());
+        for subtree_node in &self.nodes {
+            let (parent, needed) = match subtree_node.parent {
+                Some(parent) => {
+                    let parent = node_ids[parent];
+                    let parent_count = tree.get_node(parent).expect("Node should exist").count;
+                    (Some(parent), subtree_node.input_rate * parent_count)
+                }
+                None => (parent_node, rate),
+            };
+
+            let production_channel = graph.production_channel(&subtree_node.structure);
+            let hourly_rate = production_channel.hourly_rate(subtree_node.structure.output.value);
+            let count = needed / hourly_rate;
+            let group = group_offset + subtree_node.group;
+            let node_id = tree.arena.new_node(StructureTreeNode {
+                structure: subtree_node.structure.clone(),
+                count,
+                active: subtree_node.active,
+                expanded: subtree_node.expanded,
+                group,
+                output: Output::new(
+                    subtree_node.structure.output.material,
+                    (hourly_rate * count).ceil() as u64,
+                ),
+            });
+            tree.groups[group].push(node_id);
+
+            match parent {
+                Some(parent) => parent.append(node_id, &mut tree.arena),
+                None => tree.roots.get_or_insert_with(Vec::new).push(node_id),
+            }
+            node_ids.push(node_id);
+        }
+    }
+}
+
+impl ResourceGraph {
+    /// The subtree for a material, working it out on first use.
+    pub(crate) fn subtree(
+        &self,
+        material: Material,
+        user_inputs: &HashSet<Material>,
+    ) -> Arc<Subtree> {
+        let mut sorted_inputs: Vec<Material> = user_inputs.iter().copied().collect();
+        sorted_inputs.sort();
+        let key = (material, sorted_inputs);
+
+        let cached = self
+            .subtrees
+            .lock()
+            .expect("Subtree cache should not be poisoned")
+            .get(&key)
+            .cloned();
+        if let Some(subtree) = cached {
+            return subtree;
+        }
+
+        // Subtrees of the inputs are looked up while this one is planned, so the cache can't
+        // stay locked.
+        let mut tree = StructureTree::default();
+        let mut stack = Vec::new();
+        self.calculate_building_counts(
+            self.production_options(material),
+            1.0,
+            &mut stack,
+            &mut tree,
+            None,
+        );
+        self.traverse_building_reqs(
+            &mut stack,
+            user_inputs,
+            &mut StockpileSupply::default(),
+            &mut tree,
+        );
+        let subtree = Arc::new(Subtree::from_tree(self, &tree, material));
+
+        self.subtrees
+            .lock()
+            .expect("Subtree cache should not be poisoned")
+            .insert(key, subtree.clone());
+
+        subtree
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::{build_structures, setup_test_structure_maps};
+
+    /// Plan a material one step at a time, without any cached subtrees.
+    fn step_by_step(rg: &ResourceGraph, material: Material, rate: f32) -> StructureTree {
+        let mut tree = StructureTree::default();
+        let mut stack = vec![(material, rate, None)];
+        while let Some((material, rate, parent_node)) = stack.pop() {
+            let options = rg.production_options(material);
+            if !options.is_empty() {
+                rg.calculate_building_counts(options, rate, &mut stack, &mut tree, parent_node);
+            }
+        }
+
+        tree
+    }
+
+    fn nodes(tree: &StructureTree) -> Vec<(StructureTreeNode, Option<NodeId>)> {
+        tree.arena
+            .iter()
+            .map(|node| (node.get().clone(), node.parent()))
+            .collect()
+    }
+
+    #[test]
+    fn test_subtree_matches_step_by_step() {
+        let rg = ResourceGraph::default();
+
+        for (material, rate) in [(Material::Shell150MM, 35), (Material::Pipe, 7)] {
+            let outputs = vec![(material, rate)].into_iter().collect();
+            let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+            let expected = step_by_step(&rg, material, rate as f32);
+
+            assert_eq!(nodes(&trees[0]), nodes(&expected));
+            assert_eq!(trees[0].groups, expected.groups);
+            assert_eq!(trees[0].roots, expected.roots);
+        }
+    }
+
+    #[test]
+    fn test_subtrees_are_reused() {
+        let structures = build_structures();
+        let (structure_map, output_map) = setup_test_structure_maps(&structures);
+        let rg = ResourceGraph::new(&structure_map, &output_map);
+
+        let coke = rg.subtree(Material::Coke, &HashSet::new());
+        let outputs = vec![(Material::ConcreteMaterials, 2)].into_iter().collect();
+        let trees = rg.calculate_factory_requirements(outputs, HashSet::new());
+        assert!(Arc::ptr_eq(
+            &coke,
+            &rg.subtree(Material::Coke, &HashSet::new())
+        ));
+
+        // Scaled to the 2 Coke an hour the Concrete needs.
+        let counts: Vec<f32> = trees[0]
+            .arena
+            .iter()
+            .filter(|node| node.get().output.material == Material::Coke)
+            .map(|node| node.get().count)
+            .collect();
+        assert_eq!(counts, vec![1.0, 2.0]);
+
+        // New preferences start a new cache.
+        let preferred = rg.clone().with_preferences(Default::default());
+        assert!(!Arc::ptr_eq(
+            &coke,
+            &preferred.subtree(Material::Coke, &HashSet::new())
+        ));
+    }
+}